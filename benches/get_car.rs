@@ -0,0 +1,120 @@
+//! Throughput of many concurrent `GET /cars/{id}` calls against the server binary.
+//!
+//! ```sh
+//! cargo bench --bench get_car
+//! BENCH_CONNECTIONS=256 BENCH_SECS=10 cargo bench --bench get_car
+//! ```
+//!
+//! The server is started on port 9100 with the in-memory store, so nothing else may listen
+//! there, and with `ANONYMOUS_READS=true`, so the requests need no credentials. Every
+//! connection sends its requests one after another (HTTP/1.1 keep-alive).
+
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+const ADDR: &str = "127.0.0.1:9100";
+// ids of the cars `MemCarStore::init` starts with
+const CAR_IDS: [u32; 3] = [1, 2, 3];
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+async fn wait_for_server() {
+    let start = Instant::now();
+    while TcpStream::connect(ADDR).await.is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "server did not listen on {}",
+            ADDR
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Send requests on one connection until `deadline`, returning the latency of each.
+async fn run_connection(n: usize, deadline: Instant) -> Vec<Duration> {
+    let stream = TcpStream::connect(ADDR).await.expect("failed to connect");
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .expect("failed to handshake");
+    tokio::spawn(conn);
+
+    let mut latencies = vec![];
+    let mut i = n;
+    while Instant::now() < deadline {
+        let id = CAR_IDS[i % CAR_IDS.len()];
+        i += 1;
+        let req = Request::get(format!("/cars/{}", id))
+            .header(hyper::header::HOST, ADDR)
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let start = Instant::now();
+        let resp = sender.send_request(req).await.expect("request failed");
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.into_body()
+            .collect()
+            .await
+            .expect("failed to read body");
+        latencies.push(start.elapsed());
+    }
+    latencies
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p) as usize]
+}
+
+#[tokio::main]
+async fn main() {
+    let connections = env_or("BENCH_CONNECTIONS", 64usize);
+    let secs = env_or("BENCH_SECS", 5u64);
+
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_rust-hands-on"))
+            .env_remove("DB_TYPE")
+            .env("ANONYMOUS_READS", "true")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start the server"),
+    );
+    wait_for_server().await;
+
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    let tasks: Vec<_> = (0..connections)
+        .map(|n| tokio::spawn(run_connection(n, deadline)))
+        .collect();
+    let mut latencies = vec![];
+    for task in tasks {
+        latencies.extend(task.await.expect("connection panicked"));
+    }
+    latencies.sort();
+
+    println!(
+        "GET /cars/{{id}}: {} connections, {} requests in {}s, {:.0} req/s, p50 {:?}, p99 {:?}",
+        connections,
+        latencies.len(),
+        secs,
+        latencies.len() as f64 / secs as f64,
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.99),
+    );
+}
@@ -0,0 +1,247 @@
+use crate::config::{Config, ConfigArgs, StoreType};
+use crate::store::{AsyncCarStore, Car, MemCarStore, SQLiteCarStore, StoreError};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A car server, see the README for the API.
+#[derive(clap::Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Print the settings, tokens redacted, and exit
+    #[arg(long, global = true)]
+    pub print_config: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Serve the HTTP API, the default
+    Serve,
+    /// Migrate the SQLite database to the latest schema and exit
+    Migrate,
+    /// Load the cars of a JSON array into the store, as `POST /cars` would
+    Seed {
+        /// JSON file, `-` for stdin
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Write all the cars of the store, ordered by id
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// file to write instead of stdout
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+pub type Store = Box<dyn AsyncCarStore + Send + Sync>;
+
+/// The store of the settings, migrated to the latest schema if it is SQLite.
+pub fn open_store(config: &Config) -> Result<Store, StoreError> {
+    Ok(match config.store.type_ {
+        StoreType::Sqlite => Box::new(SQLiteCarStore::open(
+            &config.store.path,
+            config.store.pool_size,
+        )?),
+        StoreType::Memory => Box::new(MemCarStore::init()),
+    })
+}
+
+pub fn migrate(config: &Config) -> Result<(), String> {
+    if config.store.type_ != StoreType::Sqlite {
+        return Err("only the sqlite store has migrations, set --db-type sqlite".to_owned());
+    }
+    let path = &config.store.path;
+    let (before, now) = SQLiteCarStore::migrate(path).map_err(|e| e.to_string())?;
+    match before == now {
+        true => println!("{} is at version {} already", path, now),
+        false => println!("migrated {} from version {} to {}", path, before, now),
+    }
+    Ok(())
+}
+
+/// Every car is validated before any is stored, and the cars are stored in one go: a car
+/// the store refuses, e.g. for a duplicate VIN, leaves the store as it was.
+pub async fn seed(config: &Config, file: &Path) -> Result<(), String> {
+    if config.store.type_ == StoreType::Memory {
+        return Err("the memory store is gone once seed exits, set --db-type sqlite".to_owned());
+    }
+    let mut content = String::new();
+    let read = match file.to_str() {
+        Some("-") => std::io::stdin().read_to_string(&mut content).map(|_| ()),
+        _ => std::fs::read_to_string(file).map(|read| content = read),
+    };
+    read.map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+    let mut cars: Vec<Car> =
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", file.display(), e))?;
+    for (n, car) in cars.iter_mut().enumerate() {
+        car.validate().map_err(|e| format!("car {}: {}", n, e))?;
+    }
+
+    let store = open_store(config).map_err(|e| e.to_string())?;
+    let ids = store
+        .create_cars(cars)
+        .await
+        .map_err(|e| format!("{}, no car is stored", e))?;
+    println!("stored {} cars", ids.len());
+    Ok(())
+}
+
+pub async fn export(
+    config: &Config,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> Result<(), String> {
+    let store = open_store(config).map_err(|e| e.to_string())?;
+    let cars = store.get_all_cars().await.map_err(|e| e.to_string())?;
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .map_err(|e| format!("failed to create {}: {}", path.display(), e))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let written = match format {
+        ExportFormat::Json => {
+            let mut out = out;
+            serde_json::to_writer_pretty(&mut out, &cars)
+                .map_err(|e| e.to_string())
+                .and_then(|()| writeln!(out).map_err(|e| e.to_string()))
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            cars.iter()
+                .try_for_each(|car| writer.serialize(car))
+                .map_err(|e| e.to_string())
+                .and_then(|()| writer.flush().map_err(|e| e.to_string()))
+        }
+    };
+    written.map_err(|e| format!("failed to export: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export, open_store, seed, ExportFormat};
+    use crate::config::{Config, StoreConfig, StoreType};
+    use std::path::{Path, PathBuf};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn sqlite_config(path: &Path) -> Config {
+        Config {
+            store: StoreConfig {
+                type_: StoreType::Sqlite,
+                path: path.to_str().unwrap().to_owned(),
+                pool_size: 1,
+            },
+            ..Config::default()
+        }
+    }
+
+    fn seed_file(name: &str, cars: serde_json::Value) -> PathBuf {
+        let path = temp_path(name);
+        std::fs::write(&path, cars.to_string()).unwrap();
+        path
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_seed() {
+        let db = temp_path("cars-seed.db");
+        let config = sqlite_config(&db);
+        let cars = seed_file(
+            "cars-seed.json",
+            serde_json::json!([
+                { "brand": "BYD", "model": "Han", "year": 2020 },
+                { "brand": "Ford", "model": "Bronco", "year": 2022, "vin": "1M8GDM9AXKP042788" },
+            ]),
+        );
+        seed(&config, &cars).await.unwrap();
+
+        // the second car takes the vin of a stored one, so the first is not stored either
+        let again = seed_file(
+            "cars-seed-again.json",
+            serde_json::json!([
+                { "brand": "BYD", "model": "Seal", "year": 2023 },
+                { "brand": "Ford", "model": "Bronco", "year": 2022, "vin": "1M8GDM9AXKP042788" },
+            ]),
+        );
+        let err = seed(&config, &again).await.unwrap_err();
+        assert!(err.starts_with("car 1: "), "{}", err);
+        let store = open_store(&config).unwrap();
+        let models: Vec<String> = store
+            .get_all_cars()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|car| car.model)
+            .collect();
+        assert_eq!(models, ["Han", "Bronco"]);
+
+        drop(store);
+        for path in [db, cars, again] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export() {
+        let db = temp_path("cars-export.db");
+        let config = sqlite_config(&db);
+        let cars = seed_file(
+            "cars-export.json",
+            serde_json::json!([
+                { "brand": "BYD", "model": "Han", "year": 2020 },
+                { "brand": "Ford", "model": "Bronco", "year": 2022, "color": "red" },
+            ]),
+        );
+        seed(&config, &cars).await.unwrap();
+
+        let json = temp_path("cars-export-out.json");
+        export(&config, ExportFormat::Json, Some(&json))
+            .await
+            .unwrap();
+        let exported: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(exported.len(), 2);
+        assert_eq!(
+            (&exported[0]["id"], &exported[0]["model"]),
+            (&1.into(), &"Han".into())
+        );
+        assert_eq!(exported[1]["color"], "red");
+
+        let csv = temp_path("cars-export-out.csv");
+        export(&config, ExportFormat::Csv, Some(&csv))
+            .await
+            .unwrap();
+        let mut reader = csv::Reader::from_path(&csv).unwrap();
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(
+            headers.iter().take(4).collect::<Vec<_>>(),
+            ["id", "brand", "model", "year"]
+        );
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            (&rows[1][0], &rows[1][2], &rows[1][3]),
+            ("2", "Bronco", "2022")
+        );
+
+        for path in [db, cars, json, csv] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use crate::middleware::auth::StaticTokens;
+use crate::store::{DEFAULT_DB_PATH, DEFAULT_POOL_SIZE};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Settings of the server, from the defaults, overridden by a TOML file, then by environment
+/// variables and command line flags, see [`ConfigArgs`].
+///
+/// ```toml
+/// listen = "0.0.0.0:9100"
+///
+/// [store]
+/// type = "sqlite"
+/// path = "cars.db"
+///
+/// [timeouts]
+/// request = 3
+///
+/// [auth]
+/// tokens = ["zenx zenx admin"]
+/// policy_file = "policy.example"
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    /// value of the `Server-Owner` response header, none if empty
+    pub server_owner: String,
+    pub store: StoreConfig,
+    pub timeouts: TimeoutsConfig,
+    pub http2: Http2Config,
+    pub auth: AuthConfig,
+    pub tls: TlsFiles,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreType {
+    #[default]
+    Memory,
+    Sqlite,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    #[serde(rename = "type")]
+    pub type_: StoreType,
+    /// SQLite database, a file path, a `file:` URI or `:memory:`
+    pub path: String,
+    /// SQLite connections, each with a worker thread
+    pub pool_size: usize,
+}
+
+/// In seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub request: u64,
+    /// of `POST /ctl/images`
+    pub ctl: u64,
+    /// for in-flight requests to finish at shutdown
+    pub drain: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Http2Config {
+    /// concurrent requests of a connection
+    pub max_streams: u32,
+    /// seconds between pings, `0` disables them
+    pub keep_alive: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// `<token> <principal> [<role>,...]` lines, as in `token_file`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<String>,
+    pub token_file: Option<PathBuf>,
+    pub jwks_file: Option<PathBuf>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    pub htpasswd_file: Option<PathBuf>,
+    pub policy_file: Option<PathBuf>,
+    /// let requests without credentials `GET` cars, which otherwise require authentication
+    /// as every other route
+    pub anonymous_reads: bool,
+}
+
+/// PEM files, TLS is on with a certificate and its key.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub client_ca_file: Option<PathBuf>,
+    /// reject the handshake of clients without a certificate of `client_ca_file`, which
+    /// otherwise may authenticate in another way
+    pub require_client_cert: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: SocketAddr::from(([0, 0, 0, 0], 9100)),
+            server_owner: "zenx".to_owned(),
+            store: StoreConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            http2: Http2Config::default(),
+            auth: AuthConfig::default(),
+            tls: TlsFiles::default(),
+        }
+    }
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            type_: StoreType::Memory,
+            path: DEFAULT_DB_PATH.to_owned(),
+            pool_size: DEFAULT_POOL_SIZE,
+        }
+    }
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        TimeoutsConfig {
+            request: 3,
+            ctl: 60,
+            drain: 30,
+        }
+    }
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        // the default of hyper
+        Http2Config {
+            max_streams: 200,
+            keep_alive: 20,
+        }
+    }
+}
+
+/// Flags overriding the settings, each falling back to an environment variable.
+#[derive(Clone, Debug, Default, clap::Args)]
+pub struct ConfigArgs {
+    /// TOML file of the settings
+    #[arg(
+        global = true,
+        long = "config",
+        env = "CONFIG_FILE",
+        value_name = "FILE"
+    )]
+    pub config_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "LISTEN", value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+    #[arg(global = true, long, env = "SERVER_OWNER")]
+    pub server_owner: Option<String>,
+    #[arg(global = true, long, env = "DB_TYPE", value_enum)]
+    pub db_type: Option<StoreType>,
+    #[arg(global = true, long, env = "DB_PATH")]
+    pub db_path: Option<String>,
+    #[arg(global = true, long, env = "DB_POOL_SIZE", value_name = "CONNECTIONS")]
+    pub db_pool_size: Option<usize>,
+    #[arg(global = true, long, env = "TIMEOUT", value_name = "SECS")]
+    pub timeout: Option<u64>,
+    #[arg(global = true, long, env = "CTL_TIMEOUT", value_name = "SECS")]
+    pub ctl_timeout: Option<u64>,
+    #[arg(global = true, long, env = "DRAIN_TIMEOUT", value_name = "SECS")]
+    pub drain_timeout: Option<u64>,
+    #[arg(global = true, long, env = "H2_MAX_STREAMS", value_name = "STREAMS")]
+    pub h2_max_streams: Option<u32>,
+    #[arg(global = true, long, env = "H2_KEEP_ALIVE", value_name = "SECS")]
+    pub h2_keep_alive: Option<u64>,
+    #[arg(global = true, long, env = "TOKEN_FILE", value_name = "FILE")]
+    pub token_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "JWKS_FILE", value_name = "FILE")]
+    pub jwks_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "JWT_ISSUER")]
+    pub jwt_issuer: Option<String>,
+    #[arg(global = true, long, env = "JWT_AUDIENCE")]
+    pub jwt_audience: Option<String>,
+    #[arg(global = true, long, env = "HTPASSWD_FILE", value_name = "FILE")]
+    pub htpasswd_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "POLICY_FILE", value_name = "FILE")]
+    pub policy_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "ANONYMOUS_READS", value_name = "BOOL")]
+    pub anonymous_reads: Option<bool>,
+    #[arg(global = true, long, env = "TLS_CERT_FILE", value_name = "FILE")]
+    pub tls_cert_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "TLS_KEY_FILE", value_name = "FILE")]
+    pub tls_key_file: Option<PathBuf>,
+    #[arg(global = true, long, env = "TLS_CLIENT_CA_FILE", value_name = "FILE")]
+    pub tls_client_ca_file: Option<PathBuf>,
+    #[arg(
+        global = true,
+        long,
+        env = "TLS_REQUIRE_CLIENT_CERT",
+        value_name = "BOOL"
+    )]
+    pub tls_require_client_cert: Option<bool>,
+}
+
+impl Config {
+    /// Read the file of `args` if any, apply the overrides of `args` and validate the result.
+    pub fn load(args: &ConfigArgs) -> Result<Config, String> {
+        let mut config = match &args.config_file {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Config, String> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn apply(&mut self, args: &ConfigArgs) {
+        fn set<T: Clone>(setting: &mut T, arg: &Option<T>) {
+            if let Some(arg) = arg {
+                *setting = arg.clone();
+            }
+        }
+        fn set_opt<T: Clone>(setting: &mut Option<T>, arg: &Option<T>) {
+            if arg.is_some() {
+                setting.clone_from(arg);
+            }
+        }
+        set(&mut self.listen, &args.listen);
+        set(&mut self.server_owner, &args.server_owner);
+        set(&mut self.store.type_, &args.db_type);
+        set(&mut self.store.path, &args.db_path);
+        set(&mut self.store.pool_size, &args.db_pool_size);
+        set(&mut self.timeouts.request, &args.timeout);
+        set(&mut self.timeouts.ctl, &args.ctl_timeout);
+        set(&mut self.timeouts.drain, &args.drain_timeout);
+        set(&mut self.http2.max_streams, &args.h2_max_streams);
+        set(&mut self.http2.keep_alive, &args.h2_keep_alive);
+        set_opt(&mut self.auth.token_file, &args.token_file);
+        set_opt(&mut self.auth.jwks_file, &args.jwks_file);
+        set_opt(&mut self.auth.jwt_issuer, &args.jwt_issuer);
+        set_opt(&mut self.auth.jwt_audience, &args.jwt_audience);
+        set_opt(&mut self.auth.htpasswd_file, &args.htpasswd_file);
+        set_opt(&mut self.auth.policy_file, &args.policy_file);
+        set(&mut self.auth.anonymous_reads, &args.anonymous_reads);
+        set_opt(&mut self.tls.cert_file, &args.tls_cert_file);
+        set_opt(&mut self.tls.key_file, &args.tls_key_file);
+        set_opt(&mut self.tls.client_ca_file, &args.tls_client_ca_file);
+        set(
+            &mut self.tls.require_client_cert,
+            &args.tls_require_client_cert,
+        );
+    }
+
+    /// Report every mistake at once, one per line.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = vec![];
+        if self.store.type_ == StoreType::Sqlite && self.store.pool_size == 0 {
+            errors.push("store.pool_size must be at least 1".to_owned());
+        }
+        if self.timeouts.request == 0 || self.timeouts.ctl == 0 {
+            errors.push("timeouts.request and timeouts.ctl must be at least 1 second".to_owned());
+        }
+        if self.http2.max_streams == 0 {
+            errors.push("http2.max_streams must be at least 1".to_owned());
+        }
+        if let Err(e) = self.auth.tokens.join("\n").parse::<StaticTokens>() {
+            errors.push(format!("auth.tokens: {}", e));
+        }
+        if self.auth.jwks_file.is_some()
+            && (self.auth.jwt_issuer.is_none() || self.auth.jwt_audience.is_none())
+        {
+            errors.push("auth.jwks_file requires auth.jwt_issuer and auth.jwt_audience".to_owned());
+        }
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            errors.push("tls.cert_file and tls.key_file go together".to_owned());
+        }
+        if self.tls.client_ca_file.is_some() && self.tls.cert_file.is_none() {
+            errors.push("tls.client_ca_file requires tls.cert_file".to_owned());
+        }
+        if self.tls.require_client_cert && self.tls.client_ca_file.is_none() {
+            errors.push("tls.require_client_cert requires tls.client_ca_file".to_owned());
+        }
+        let files = [
+            ("auth.token_file", &self.auth.token_file),
+            ("auth.jwks_file", &self.auth.jwks_file),
+            ("auth.htpasswd_file", &self.auth.htpasswd_file),
+            ("auth.policy_file", &self.auth.policy_file),
+            ("tls.cert_file", &self.tls.cert_file),
+            ("tls.key_file", &self.tls.key_file),
+            ("tls.client_ca_file", &self.tls.client_ca_file),
+        ];
+        for (name, path) in files {
+            if let Some(path) = path.as_ref().filter(|path| !path.is_file()) {
+                errors.push(format!("{}: no file {}", name, path.display()));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("\n")),
+        }
+    }
+
+    /// A copy to show, without the secrets of `auth.tokens`.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        for line in &mut config.auth.tokens {
+            let rest = line
+                .trim()
+                .split_once(char::is_whitespace)
+                .map(|(_, rest)| rest);
+            *line = format!("<redacted> {}", rest.unwrap_or_default().trim());
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, ConfigArgs, StoreType};
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        config: ConfigArgs,
+    }
+
+    /// The arguments `args` with the environment `env` only, whatever that of the process: the
+    /// variables of the settings are renamed with the prefix `CONFIG_TEST_`, and clap reads a
+    /// variable as it is set on the argument, so they are only set meanwhile.
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> ConfigArgs {
+        let prefixed = |name: &str| format!("CONFIG_TEST_{}", name);
+        for (name, value) in env {
+            std::env::set_var(prefixed(name), value);
+        }
+        let cmd = Cli::command().mut_args(|arg| match arg.get_env() {
+            Some(name) => {
+                let name = prefixed(name.to_str().unwrap());
+                arg.env(&*Box::leak(name.into_boxed_str()))
+            }
+            None => arg,
+        });
+        for (name, _) in env {
+            std::env::remove_var(prefixed(name));
+        }
+        let matches = cmd.try_get_matches_from(args);
+        Cli::from_arg_matches(&matches.unwrap()).unwrap().config
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut config: Config = toml::from_str(
+            "server_owner = \"ops\"\n[store]\ntype = \"sqlite\"\n[timeouts]\nrequest = 5\n\
+             [auth]\ntokens = [\"s3cr3t ci admin\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.store.type_, StoreType::Sqlite);
+        assert_eq!(config.timeouts.ctl, 60);
+
+        // flags over the environment over the file
+        let env = [("TIMEOUT", "9"), ("CTL_TIMEOUT", "30")];
+        config.apply(&parse(
+            &["cars", "--timeout", "7", "--listen", "127.0.0.1:80"],
+            &env,
+        ));
+        assert_eq!(config.timeouts.request, 7);
+        assert_eq!(config.timeouts.ctl, 30);
+        assert_eq!(config.listen.port(), 80);
+        assert_eq!(config.server_owner, "ops");
+        // no variable of the process leaks in
+        assert!(parse(&["cars"], &[]).timeout.is_none());
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(config.redacted().auth.tokens, ["<redacted> ci admin"]);
+
+        config.timeouts.request = 0;
+        config.auth.jwks_file = Some("jwks.json".into());
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors.lines().count(), 3, "{}", errors);
+        assert!(toml::from_str::<Config>("port = 9100").is_err());
+    }
+}
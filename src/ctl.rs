@@ -1,16 +1,17 @@
 use bytes::Buf;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::process::{Command, Output, Stdio};
+use std::process::{Output, Stdio};
 use std::result::Result;
 use std::str;
+use tokio::process::Command;
 
 fn run<T: Default + DeserializeOwned>(
     out: Result<Output, std::io::Error>,
     action: String,
 ) -> Result<T, String> {
     match out {
-        Err(e) => Err(format!("err output {}", e.to_string())),
+        Err(e) => Err(format!("err output {}", e)),
         Ok(o) => {
             if o.status.success() {
                 // action doesn't need stdout json decoding
@@ -35,9 +36,15 @@ fn run<T: Default + DeserializeOwned>(
     }
 }
 
-pub fn push_image(tag: String) -> Result<(), String> {
-    let ret = Command::new("docker").arg("push").arg(tag).output();
-    Ok(run::<()>(ret, "docker push".to_owned())?)
+// commands are killed when their future is dropped, e.g. at the deadline of the request
+pub async fn push_image(tag: String) -> Result<(), String> {
+    let ret = Command::new("docker")
+        .arg("push")
+        .arg(tag)
+        .kill_on_drop(true)
+        .output()
+        .await;
+    run::<()>(ret, "docker push".to_owned())
 }
 
 #[derive(Serialize, Deserialize, std::fmt::Debug)]
@@ -53,20 +60,30 @@ pub struct Image {
 }
 
 // list_images by docker image ls --format "{{json . }}" | jq -s
-pub fn list_images() -> Result<Vec<Image>, String> {
-    let dockerchild = Command::new("docker")
+pub async fn list_images() -> Result<Vec<Image>, String> {
+    let mut dockerchild = Command::new("docker")
         .arg("image")
         .arg("ls")
         .arg("--format")
         .arg("{{json . }}")
         .stdout(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
-        .unwrap();
+        .map_err(|e| format!("err spawn docker: {}", e))?;
+    let dockerout: Stdio = dockerchild
+        .stdout
+        .take()
+        .unwrap()
+        .try_into()
+        .map_err(|e| format!("err pipe docker: {}", e))?;
 
     let o = Command::new("jq")
-        .stdin(Stdio::from(dockerchild.stdout.unwrap()))
+        .stdin(dockerout)
         .arg("-s")
-        .output();
+        .kill_on_drop(true)
+        .output()
+        .await;
+    let _ = dockerchild.wait().await;
 
-    Ok(run::<Vec<Image>>(o, "docker images".to_owned())?)
+    run::<Vec<Image>>(o, "docker images".to_owned())
 }
@@ -1,3 +1,4 @@
+use futures_util::FutureExt;
 use std::{future::Future, pin::Pin};
 
 #[derive(Clone, Debug)]
@@ -9,11 +10,9 @@ impl Svc {
         vec!["a".to_owned()]
     }
 
-    fn b<'a>(&'a self) -> impl Future<Output = Vec<String>> + 'a {
-        async move {
-            print!("{:?}", self);
-            vec!["b".to_owned()]
-        }
+    async fn b(&self) -> Vec<String> {
+        print!("{:?}", self);
+        vec!["b".to_owned()]
     }
 }
 
@@ -135,4 +134,9 @@ fn test_box_svc() {
     router.insert("/a/boxed/regions/:region", ha2).unwrap();
     router.insert("/b/boxed/cars/:car_id", ha3).unwrap();
     router.insert("/closore_boxed/cars/:car_id", ha4).unwrap();
+
+    // the boxed handlers borrow for 'static, so do the handler and the svc it is called with
+    static SVC: Svc = Svc;
+    let ha = Box::leak(Box::new(svc_fn(Svc::b).boxed()));
+    assert_eq!(ha.call(&SVC).now_or_never(), Some(vec!["b".to_owned()]));
 }
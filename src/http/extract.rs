@@ -0,0 +1,393 @@
+use super::handler::{Context, FromRequest, FromRequestParts};
+use super::problem::Problem;
+use bytes::Buf;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::http::{request::Parts, HeaderMap};
+use hyper::StatusCode;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// Variables of the matched route, e.g. `Path<u32>` for `/cars/{id}`, percent-decoded.
+///
+/// A route with several variables is extracted as a struct or a map keyed by their names, or
+/// as a tuple in the order of the route.
+pub struct Path<T>(pub T);
+
+/// Query string of the url, e.g. `Query<ListCarsParams>` for `/cars?brand=Ford`.
+pub struct Query<T>(pub T);
+
+/// JSON request body, consuming it, so it can only be the last argument.
+pub struct Json<T>(pub T);
+
+/// A copy of the request headers.
+pub struct Headers(pub HeaderMap);
+
+/// The struct the router has been built with, `Svc` for this server.
+pub struct State<S>(pub S);
+
+/// A value a middleware inserted into the request extensions, see [`Context::extensions`].
+///
+/// A missing value is a bug of the middleware stack, so it answers `500`.
+pub struct Extension<T>(pub T);
+
+impl<STRUCT, T: DeserializeOwned> FromRequestParts<STRUCT> for Path<T> {
+    fn from_request_parts(_: &Parts, ctx: &Context, _: &STRUCT) -> Result<Self, Problem> {
+        let invalid_path = |detail: String| {
+            Problem::new(StatusCode::BAD_REQUEST)
+                .with_type("/problems/invalid-path", "Invalid Path Parameter")
+                .with_detail(detail)
+        };
+        let vars = ctx
+            .vars
+            .iter()
+            .map(|(name, value)| {
+                let value = percent_encoding::percent_decode_str(value)
+                    .decode_utf8()
+                    .map_err(|_| invalid_path(format!("{} is not percent-encoded UTF-8", name)))?;
+                Ok((name.clone(), value.into_owned()))
+            })
+            .collect::<Result<Vec<_>, Problem>>()?;
+        T::deserialize(PathDeserializer(&vars))
+            .map(Path)
+            .map_err(|e| invalid_path(e.to_string()))
+    }
+}
+
+impl<STRUCT, T: DeserializeOwned> FromRequestParts<STRUCT> for Query<T> {
+    fn from_request_parts(parts: &Parts, _: &Context, _: &STRUCT) -> Result<Self, Problem> {
+        serde_urlencoded::from_str(parts.uri.query().unwrap_or_default())
+            .map(Query)
+            .map_err(|e| {
+                Problem::new(StatusCode::BAD_REQUEST)
+                    .with_type("/problems/invalid-query", "Invalid Query String")
+                    .with_detail(e.to_string())
+            })
+    }
+}
+
+impl<STRUCT> FromRequestParts<STRUCT> for Headers {
+    fn from_request_parts(parts: &Parts, _: &Context, _: &STRUCT) -> Result<Self, Problem> {
+        Ok(Headers(parts.headers.clone()))
+    }
+}
+
+impl<STRUCT: Clone> FromRequestParts<STRUCT> for State<STRUCT> {
+    fn from_request_parts(_: &Parts, _: &Context, s: &STRUCT) -> Result<Self, Problem> {
+        Ok(State(s.clone()))
+    }
+}
+
+impl<STRUCT, T: Clone + Send + Sync + 'static> FromRequestParts<STRUCT> for Extension<T> {
+    fn from_request_parts(_: &Parts, ctx: &Context, _: &STRUCT) -> Result<Self, Problem> {
+        ctx.extension::<T>().cloned().map(Extension).ok_or_else(|| {
+            Problem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_detail(format!("missing extension {}", std::any::type_name::<T>()))
+        })
+    }
+}
+
+impl<STRUCT: Sync, T: DeserializeOwned> FromRequest<STRUCT> for Json<T> {
+    async fn from_request(
+        req: hyper::Request<Incoming>,
+        _: &Context,
+        _: &STRUCT,
+    ) -> Result<Self, Problem> {
+        let invalid_body = |detail: String| {
+            Problem::new(StatusCode::BAD_REQUEST)
+                .with_type("/problems/invalid-body", "Invalid Request Body")
+                .with_detail(detail)
+        };
+        let bytes = req
+            .collect()
+            .await
+            .map_err(|e| invalid_body(e.to_string()))?;
+        let mut de = serde_json::Deserializer::from_reader(bytes.aggregate().reader());
+        T::deserialize(&mut de)
+            .map(Json)
+            .map_err(|e| invalid_body(format!("failed to parse request body: {}", e)))
+    }
+}
+
+/// Deserialize route variables, as a map, a sequence or, if there is only one, as a plain
+/// value.
+struct PathDeserializer<'a>(&'a [(String, String)]);
+
+impl<'a> PathDeserializer<'a> {
+    fn single(&self) -> Result<ParamDeserializer<'a>, de::value::Error> {
+        match self.0 {
+            [(_, value)] => Ok(ParamDeserializer(value)),
+            _ => Err(de::Error::custom(format!(
+                "expect a single path parameter, got {}",
+                self.0.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! deserialize_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for PathDeserializer<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let vars = self
+            .0
+            .iter()
+            .map(|(name, value)| (name.as_str(), ParamDeserializer(value)));
+        visitor.visit_map(MapDeserializer::new(vars))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut vars =
+            SeqDeserializer::new(self.0.iter().map(|(_, value)| ParamDeserializer(value)));
+        let value = visitor.visit_seq(&mut vars)?;
+        // fails if the tuple has fewer elements than the route variables
+        vars.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    deserialize_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_option
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct map struct identifier ignored_any
+    }
+}
+
+/// A single route variable, parsed according to the type it is deserialized into.
+struct ParamDeserializer<'a>(&'a str);
+
+impl<'de> IntoDeserializer<'de, de::value::Error> for ParamDeserializer<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_param {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ParamDeserializer<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    parse_param! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct newtype_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FromRequest, FromRequestParts, Json, Path, Query};
+    use crate::http::handler::Context;
+    use crate::http::problem::Problem;
+    use crate::http::{full, oneshot};
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::{header, Request, StatusCode};
+    use serde::Deserialize;
+
+    fn path<T: serde::de::DeserializeOwned>(vars: &[(&str, &str)]) -> Result<T, Problem> {
+        let (parts, ()) = Request::new(()).into_parts();
+        let ctx = Context {
+            vars: vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        Path::<T>::from_request_parts(&parts, &ctx, &()).map(|Path(value)| value)
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct CarImage {
+        id: u32,
+        name: String,
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path::<u32>(&[("id", "7")]).unwrap(), 7);
+        assert_eq!(
+            path::<(u32, String)>(&[("id", "7"), ("name", "front")]).unwrap(),
+            (7, "front".to_owned())
+        );
+        assert_eq!(
+            path::<CarImage>(&[("name", "front"), ("id", "7")]).unwrap(),
+            CarImage {
+                id: 7,
+                name: "front".to_owned()
+            }
+        );
+        assert_eq!(
+            path::<String>(&[("name", "left%20side%2Fv2")]).unwrap(),
+            "left side/v2"
+        );
+
+        let problem = path::<u32>(&[("id", "seven")]).unwrap_err();
+        assert_eq!(problem.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(problem.type_, "/problems/invalid-path");
+        assert!(problem.detail.unwrap().contains("seven"));
+        let problem = path::<CarImage>(&[("id", "7")]).unwrap_err();
+        assert!(problem.detail.unwrap().contains("missing field `name`"));
+        assert!(path::<u32>(&[]).is_err());
+        assert!(path::<(u32, String)>(&[("id", "7")]).is_err());
+        assert!(path::<(u32,)>(&[("id", "7"), ("name", "front")]).is_err());
+        assert!(path::<String>(&[("name", "%FF")]).is_err());
+    }
+
+    #[test]
+    fn test_query() {
+        #[derive(Deserialize)]
+        struct Params {
+            brand: String,
+            limit: Option<usize>,
+        }
+        let query = |uri: &str| {
+            let (parts, ()) = Request::get(uri).body(()).unwrap().into_parts();
+            Query::<Params>::from_request_parts(&parts, &Context::default(), &())
+        };
+        let Query(params) = query("/cars?brand=Land%20Rover&limit=2").ok().unwrap();
+        assert_eq!(
+            (params.brand.as_str(), params.limit),
+            ("Land Rover", Some(2))
+        );
+        let problem = query("/cars?brand=Ford&limit=two").err().unwrap();
+        assert_eq!(problem.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(problem.type_, "/problems/invalid-query");
+    }
+
+    /// Answer `204` once the body parses as a `CarImage`, or its problem.
+    async fn json(content_type: Option<&str>, body: &'static str) -> hyper::Response<Bytes> {
+        let svc = hyper::service::service_fn(|req| async move {
+            let resp = match Json::<CarImage>::from_request(req, &Context::default(), &()).await {
+                Ok(_) => hyper::Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(full(""))
+                    .unwrap(),
+                Err(problem) => problem.into_response_with(full),
+            };
+            Ok::<_, std::convert::Infallible>(resp)
+        });
+        let mut req = Request::post("/cars/7/images");
+        if let Some(content_type) = content_type {
+            req = req.header(header::CONTENT_TYPE, content_type);
+        }
+        oneshot(svc, req.body(Full::new(Bytes::from(body))).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_json() {
+        let car = r#"{"id": 7, "name": "front"}"#;
+        // the body is parsed whatever its content type, as curl -d sends a form
+        for content_type in [
+            Some("application/json"),
+            Some("application/merge-patch+json"),
+            Some("application/x-www-form-urlencoded"),
+            None,
+        ] {
+            assert_eq!(
+                json(content_type, car).await.status(),
+                StatusCode::NO_CONTENT,
+                "{:?}",
+                content_type
+            );
+        }
+
+        let resp = json(Some("application/json"), r#"{"id": "7"}"#).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let problem: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(problem["type"], "/problems/invalid-body");
+    }
+}
@@ -1,7 +1,30 @@
-use std::{future::Future, pin::Pin};
+use super::problem::Problem;
+use super::{full, BoxBody};
+use hyper::body::Incoming;
+use hyper::http::{request::Parts, Extensions};
+use std::{future::Future, marker::PhantomData, pin::Pin};
 
+#[derive(Clone, Default)]
 pub struct Context {
-    pub vars: std::collections::HashMap<String, String>,
+    /// variables of the matched route, named and in the order of the route, as sent
+    pub vars: Vec<(String, String)>,
+    /// Typed values middlewares inserted into `Request::extensions`, e.g. the authenticated
+    /// principal, read with [`Context::extension`] or the [`super::Extension`] extractor.
+    pub extensions: Extensions,
+}
+
+impl Context {
+    /// Context of a request matching a route with `vars`, with a copy of its extensions.
+    pub fn new<B>(vars: Vec<(String, String)>, req: &hyper::Request<B>) -> Context {
+        Context {
+            vars,
+            extensions: req.extensions().clone(),
+        }
+    }
+
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
 }
 
 pub trait Handler<STRUCT, Request> {
@@ -39,14 +62,37 @@ where
 }
 // === ported from tower and known how it means.
 
-#[derive(Copy, Clone)]
-pub struct HandlerFn<F>(F);
+/// `M` tells apart the kinds of functions a handler can be made of, it is always inferred:
+/// `(Context,)` for `FnMut(STRUCT, Context, Request)`, or the extractor types of the arguments.
+pub struct HandlerFn<F, M> {
+    f: F,
+    _marker: PhantomData<fn() -> M>,
+}
 
-pub fn handler_fn<F>(f: F) -> HandlerFn<F> {
-    HandlerFn(f)
+impl<F: Clone, M> Clone for HandlerFn<F, M> {
+    fn clone(&self) -> Self {
+        handler_fn(self.f.clone())
+    }
 }
 
-impl<F, Fut, STRUCT, Request, Response> Handler<STRUCT, Request> for HandlerFn<F>
+impl<F: Copy, M> Copy for HandlerFn<F, M> {}
+
+/// Make a handler of either a function taking the struct, the context and the raw request,
+/// or a function taking up to 6 extractors, e.g.
+///
+/// ```ignore
+/// async fn get_car(State(svc): State<Svc>, Path(id): Path<u32>) -> Response<BoxBody>
+/// ```
+///
+/// The last argument may consume the request body, such as [`super::Json`].
+pub fn handler_fn<F, M>(f: F) -> HandlerFn<F, M> {
+    HandlerFn {
+        f,
+        _marker: PhantomData,
+    }
+}
+
+impl<F, Fut, STRUCT, Request, Response> Handler<STRUCT, Request> for HandlerFn<F, (Context,)>
 where
     F: FnMut(STRUCT, Context, Request) -> Fut,
     Fut: Future<Output = Response>,
@@ -54,10 +100,108 @@ where
     type Response = Response;
     type Future = Fut;
     fn call(&mut self, s: STRUCT, ctx: Context, r: Request) -> Self::Future {
-        (self.0)(s, ctx, r)
+        (self.f)(s, ctx, r)
+    }
+}
+
+/// Extract a handler argument from the request head, the body is left untouched.
+///
+/// A failed extraction answers the problem right away, the handler is never called.
+pub trait FromRequestParts<STRUCT>: Sized {
+    fn from_request_parts(parts: &Parts, ctx: &Context, s: &STRUCT) -> Result<Self, Problem>;
+}
+
+/// Extract a handler argument from the whole request, only the last argument may do so.
+pub trait FromRequest<STRUCT, M = ()>: Sized {
+    fn from_request(
+        req: hyper::Request<Incoming>,
+        ctx: &Context,
+        s: &STRUCT,
+    ) -> impl Future<Output = Result<Self, Problem>> + Send;
+}
+
+/// Marks the [`FromRequest`] impl of extractors only reading the request head.
+pub struct ViaParts;
+
+impl<STRUCT, T> FromRequest<STRUCT, ViaParts> for T
+where
+    T: FromRequestParts<STRUCT> + Send,
+    STRUCT: Sync,
+{
+    async fn from_request(
+        req: hyper::Request<Incoming>,
+        ctx: &Context,
+        s: &STRUCT,
+    ) -> Result<Self, Problem> {
+        let (parts, _) = req.into_parts();
+        T::from_request_parts(&parts, ctx, s)
     }
 }
 
+impl<F, Fut, STRUCT> Handler<STRUCT, hyper::Request<Incoming>> for HandlerFn<F, ()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = hyper::Response<BoxBody>>,
+{
+    type Response = hyper::Response<BoxBody>;
+    type Future = Fut;
+    fn call(&mut self, _: STRUCT, _: Context, _: hyper::Request<Incoming>) -> Self::Future {
+        (self.f)()
+    }
+}
+
+macro_rules! impl_handler_fn {
+    ( $($ty:ident),* ; $last:ident ) => {
+        #[allow(non_snake_case)]
+        impl<F, Fut, STRUCT, M, $($ty,)* $last> Handler<STRUCT, hyper::Request<Incoming>>
+            for HandlerFn<F, (M, $($ty,)* $last,)>
+        where
+            F: FnMut($($ty,)* $last) -> Fut + Clone + Send + 'static,
+            Fut: Future<Output = hyper::Response<BoxBody>> + Send,
+            STRUCT: Send + Sync + 'static,
+            $( $ty: FromRequestParts<STRUCT> + Send, )*
+            $last: FromRequest<STRUCT, M> + Send,
+        {
+            type Response = hyper::Response<BoxBody>;
+            type Future = ABoxFuture<'static, hyper::Response<BoxBody>>;
+
+            fn call(
+                &mut self,
+                s: STRUCT,
+                ctx: Context,
+                req: hyper::Request<Incoming>,
+            ) -> Self::Future {
+                let mut f = self.f.clone();
+                let mut ctx = ctx;
+                Box::pin(async move {
+                    let (parts, body) = req.into_parts();
+                    // route layers may have inserted values since the context was made
+                    ctx.extensions = parts.extensions.clone();
+                    $(
+                        let $ty = match $ty::from_request_parts(&parts, &ctx, &s) {
+                            Ok(value) => value,
+                            Err(problem) => return problem.into_response_with(full),
+                        };
+                    )*
+                    let req = hyper::Request::from_parts(parts, body);
+                    let $last = match $last::from_request(req, &ctx, &s).await {
+                        Ok(value) => value,
+                        Err(problem) => return problem.into_response_with(full),
+                    };
+                    f($($ty,)* $last).await
+                })
+            }
+        }
+    };
+}
+
+impl_handler_fn!(; T1);
+impl_handler_fn!(T1; T2);
+impl_handler_fn!(T1, T2; T3);
+impl_handler_fn!(T1, T2, T3; T4);
+impl_handler_fn!(T1, T2, T3, T4; T5);
+impl_handler_fn!(T1, T2, T3, T4, T5; T6);
+
 trait HandlerExt<STRUCT, Request>: Handler<STRUCT, Request> {
     fn map_future<F, Fut, T>(self, f: F) -> MapFuture<Self, F>
     where
@@ -67,22 +211,6 @@ trait HandlerExt<STRUCT, Request>: Handler<STRUCT, Request> {
     {
         MapFuture::new(self, f)
     }
-
-    fn boxed(self) -> BoxHandler<STRUCT, Request, Self::Response>
-    where
-        Self: Sized + Send + 'static,
-        Self::Future: Send + 'static,
-    {
-        BoxHandler::new(self)
-    }
-
-    fn boxed_clone(self) -> BoxCloneHandler<STRUCT, Request, Self::Response>
-    where
-        Self: Clone + Sized + Send + 'static,
-        Self::Future: Send + 'static,
-    {
-        BoxCloneHandler::new(self)
-    }
 }
 
 impl<T: ?Sized, STRUCT, Request> HandlerExt<STRUCT, Request> for T where T: Handler<STRUCT, Request> {}
@@ -151,14 +279,16 @@ impl<STRUCT, Request, Response> Handler<STRUCT, Request> for BoxHandler<STRUCT,
 // ported from https://github.com/tower-rs/tower/pull/615/files
 pub type ABoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>; // box future with lifetime parameter a'
 
+/// A boxed handler which is also `Sync`, so the route tables can be shared by all the
+/// connections without a lock: each request calls its own clone.
 pub struct BoxCloneHandler<S, R, U>(
-    Box<dyn CloneHandler<S, R, Response = U, Future = ABoxFuture<'static, U>> + Send>,
+    Box<dyn CloneHandler<S, R, Response = U, Future = ABoxFuture<'static, U>> + Send + Sync>,
 );
 
 impl<STRUCT, Request, Response> BoxCloneHandler<STRUCT, Request, Response> {
     pub fn new<S>(inner: S) -> Self
     where
-        S: Handler<STRUCT, Request, Response = Response> + Clone + Send + 'static,
+        S: Handler<STRUCT, Request, Response = Response> + Clone + Send + Sync + 'static,
         S::Future: Send + 'static,
     {
         let inner = inner.map_future(|f| Box::pin(f) as _);
@@ -185,17 +315,18 @@ impl<STRUCT, Request, Response> Clone for BoxCloneHandler<STRUCT, Request, Respo
 trait CloneHandler<S, R>: Handler<S, R> {
     fn clone_box(
         &self,
-    ) -> Box<dyn CloneHandler<S, R, Response = Self::Response, Future = Self::Future> + Send>;
+    ) -> Box<dyn CloneHandler<S, R, Response = Self::Response, Future = Self::Future> + Send + Sync>;
 }
 
 impl<STRUCT, Request, T> CloneHandler<STRUCT, Request> for T
 where
-    T: Handler<STRUCT, Request> + Send + Clone + 'static,
+    T: Handler<STRUCT, Request> + Send + Sync + Clone + 'static,
 {
     fn clone_box(
         &self,
-    ) -> Box<dyn CloneHandler<STRUCT, Request, Response = T::Response, Future = T::Future> + Send>
-    {
+    ) -> Box<
+        dyn CloneHandler<STRUCT, Request, Response = T::Response, Future = T::Future> + Send + Sync,
+    > {
         Box::new(self.clone())
     }
 }
@@ -124,7 +124,7 @@ impl IntoResponse for Cow<'static, str> {
         let mut res = Full::from(self).into_response();
         res.headers_mut().insert(
             header::CONTENT_TYPE,
-            HeaderValue::from_static("text/plain; charset=utf-8".as_ref()),
+            HeaderValue::from_static("text/plain; charset=utf-8"),
         );
         res
     }
@@ -0,0 +1,112 @@
+use super::handler::{ABoxFuture, BoxCloneHandler, Context, Handler};
+use std::{convert::Infallible, sync::Arc};
+
+/// Middleware of a single route or of the routes under a prefix, see [`layer_fn`].
+pub type RouteLayer<STRUCT, Request, Response> = Arc<
+    dyn Fn(BoxCloneHandler<STRUCT, Request, Response>) -> BoxCloneHandler<STRUCT, Request, Response>
+        + Send
+        + Sync,
+>;
+
+/// A handler bound to the struct and context of one request, so that it is a hyper service
+/// which the service middlewares in `crate::middleware` can wrap.
+pub struct HandlerService<STRUCT, Request, Response> {
+    handler: BoxCloneHandler<STRUCT, Request, Response>,
+    s: STRUCT,
+    ctx: Context,
+}
+
+impl<STRUCT: Clone, Request, Response> Clone for HandlerService<STRUCT, Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            s: self.s.clone(),
+            ctx: self.ctx.clone(),
+        }
+    }
+}
+
+impl<STRUCT, Request, Response> hyper::service::Service<Request>
+    for HandlerService<STRUCT, Request, Response>
+where
+    STRUCT: Clone,
+    Response: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = ABoxFuture<'static, Result<Response, Infallible>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let fut = self
+            .handler
+            .clone()
+            .call(self.s.clone(), self.ctx.clone(), req);
+        Box::pin(async move { Ok(fut.await) })
+    }
+}
+
+/// Make a route layer of a service middleware, e.g.
+///
+/// ```ignore
+/// layer_fn(|inner| Timeout::new(inner, Duration::from_secs(30)))
+/// ```
+///
+/// The middleware is built around the handler on every request, so it must be cheap to
+/// construct, and it must turn its own errors into responses as routes cannot fail.
+pub fn layer_fn<F, S, STRUCT, Request, Response>(f: F) -> RouteLayer<STRUCT, Request, Response>
+where
+    F: Fn(HandlerService<STRUCT, Request, Response>) -> S + Clone + Send + Sync + 'static,
+    S: hyper::service::Service<Request, Response = Response, Error = Infallible> + Send + 'static,
+    S::Future: Send + 'static,
+    STRUCT: Clone + Send + 'static,
+    Request: Send + 'static,
+    Response: Send + 'static,
+{
+    Arc::new(move |handler| {
+        BoxCloneHandler::new(Layered {
+            f: f.clone(),
+            handler,
+        })
+    })
+}
+
+struct Layered<F, STRUCT, Request, Response> {
+    f: F,
+    handler: BoxCloneHandler<STRUCT, Request, Response>,
+}
+
+impl<F: Clone, STRUCT, Request, Response> Clone for Layered<F, STRUCT, Request, Response> {
+    fn clone(&self) -> Self {
+        Self {
+            f: self.f.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<F, S, STRUCT, Request, Response> Handler<STRUCT, Request>
+    for Layered<F, STRUCT, Request, Response>
+where
+    F: Fn(HandlerService<STRUCT, Request, Response>) -> S,
+    S: hyper::service::Service<Request, Response = Response, Error = Infallible> + Send + 'static,
+    S::Future: Send + 'static,
+    Request: Send + 'static,
+{
+    type Response = Response;
+    type Future = ABoxFuture<'static, Response>;
+
+    fn call(&mut self, s: STRUCT, ctx: Context, req: Request) -> Self::Future {
+        let svc = (self.f)(HandlerService {
+            handler: self.handler.clone(),
+            s,
+            ctx,
+        });
+        let fut = svc.call(req);
+        Box::pin(async move {
+            match fut.await {
+                Ok(resp) => resp,
+                Err(never) => match never {},
+            }
+        })
+    }
+}
@@ -1,3 +1,4 @@
+use futures_util::FutureExt;
 use std::{future::Future, pin::Pin};
 
 #[derive(Clone, Debug)]
@@ -20,11 +21,9 @@ impl Svc {
 
     // two fn b(&Svc)-> impl Future<Output = Vec<String>> are different different opaque types
     #[allow(dead_code)]
-    fn b<'a>(self) -> impl Future<Output = Vec<String>> + 'a {
-        async move {
-            print!("{:?}", self);
-            vec!["b".to_owned()]
-        }
+    async fn b(self) -> Vec<String> {
+        print!("{:?}", self);
+        vec!["b".to_owned()]
     }
 
     #[allow(dead_code)]
@@ -188,4 +187,7 @@ fn test_box_svc() {
         }
         Err(e) => eprintln!("route match err {}", e),
     }
+
+    let ha = router.at_mut("/b/boxed/cars/:car_id").unwrap().value;
+    assert_eq!(ha.call(Svc).now_or_never(), Some(vec!["b".to_owned()]));
 }
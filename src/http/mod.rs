@@ -1,11 +1,24 @@
+#[cfg(test)]
 mod babe_svc_ref;
 mod handler;
-mod lifetime_handler_sucks;
+#[cfg(test)]
 mod mock_tower_svc;
 use http_body_util::{BodyExt, Full};
 
+mod extract;
+mod layer;
+mod router;
+pub use self::extract::{Extension, Headers, Json, Path, Query, State};
+#[allow(unused_imports)]
 pub use self::handler::{handler_fn, BoxCloneHandler, Context, Handler};
+pub use self::layer::{layer_fn, RouteLayer};
+#[allow(unused_imports)]
+pub use self::router::{
+    delete, get, patch, post, put, MatchedPath, MethodRouter, RouteMatch, Router, RouterError,
+    Routes,
+};
 pub mod into_response;
+pub mod problem;
 
 #[allow(dead_code)]
 pub fn type_of<T>(_: &T) -> &str {
@@ -30,3 +43,36 @@ fn mk_err_response<T: Into<bytes::Bytes>>(
         .body(full(body))
         .unwrap()
 }
+
+/// Send `req` to `svc` over an in-memory HTTP/1.1 connection and collect the response, as
+/// `tower::ServiceExt::oneshot` would, since handlers take the `Incoming` body of hyper.
+#[cfg(test)]
+pub async fn oneshot<S, B>(
+    svc: S,
+    req: hyper::Request<Full<bytes::Bytes>>,
+) -> hyper::Response<bytes::Bytes>
+where
+    S: hyper::service::Service<
+            hyper::Request<hyper::body::Incoming>,
+            Response = hyper::Response<B>,
+        > + Send
+        + 'static,
+    S::Future: Send,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    use hyper_util::rt::TokioIo;
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(
+        hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(server), svc),
+    );
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(client))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let (parts, body) = sender.send_request(req).await.unwrap().into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    hyper::Response::from_parts(parts, body)
+}
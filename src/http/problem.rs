@@ -0,0 +1,78 @@
+use super::into_response::{boxed, IntoResponse, Response};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{header, http::HeaderValue, StatusCode};
+use serde::Serialize;
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem details of an error response (RFC 7807), serialized as `application/problem+json`.
+///
+/// `type` defaults to `about:blank`, in which case `title` is the reason phrase of `status`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode) -> Problem {
+        Problem {
+            type_: "about:blank".to_owned(),
+            title: status.canonical_reason().unwrap_or_default().to_owned(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+        }
+    }
+
+    /// Set a URI reference identifying the problem type, together with its short summary.
+    pub fn with_type(mut self, type_: impl Into<String>, title: impl Into<String>) -> Problem {
+        self.type_ = type_.into();
+        self.title = title.into();
+        self
+    }
+
+    /// Explanation specific to this occurrence of the problem, empty strings are left out.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Problem {
+        let detail = detail.into();
+        self.detail = (!detail.is_empty()).then_some(detail);
+        self
+    }
+
+    /// URI reference of the occurrence, usually the request path.
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Problem {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Build the response with any body type, `body` wraps the serialized problem.
+    pub fn into_response_with<B>(self, body: impl FnOnce(Bytes) -> B) -> hyper::Response<B> {
+        // a struct of strings and numbers always serializes
+        let json = serde_json::to_vec(&self).unwrap();
+        hyper::Response::builder()
+            .status(self.status())
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+            )
+            .body(body(Bytes::from(json)))
+            .unwrap()
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        self.into_response_with(|json| boxed(Full::new(json)))
+    }
+}
@@ -0,0 +1,368 @@
+use super::handler::{handler_fn, BoxCloneHandler, Handler, HandlerFn};
+use super::layer::RouteLayer;
+use hyper::Method;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+type Table<STRUCT, Request, Response> =
+    matchit::Router<(MatchedPath, BoxCloneHandler<STRUCT, Request, Response>)>;
+
+/// Route tables built by [`Router::build`], one per method.
+pub struct Routes<STRUCT, Request, Response> {
+    tables: HashMap<Method, Table<STRUCT, Request, Response>>,
+}
+
+/// The route pattern a request matched, e.g. `/cars/{id}`, in the request extensions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedPath(pub Arc<str>);
+
+/// Outcome of looking up a request in [`Routes`].
+pub enum RouteMatch<'a, STRUCT, Request, Response> {
+    Found {
+        route: &'a MatchedPath,
+        handler: &'a BoxCloneHandler<STRUCT, Request, Response>,
+        params: Vec<(String, String)>,
+    },
+    /// an `OPTIONS` request without a route of its own, answered with these methods
+    Options(Vec<Method>),
+    /// the path is routed, but not for this method, which is one of these
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+impl<STRUCT, Request, Response> Routes<STRUCT, Request, Response> {
+    /// Methods routed for `path`, including `HEAD` if `GET` is, and `OPTIONS`.
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = self
+            .tables
+            .iter()
+            .filter(|(_, table)| table.at(path).is_ok())
+            .map(|(method, _)| method.clone())
+            .collect();
+        if methods.is_empty() {
+            return methods;
+        }
+        for implied in [Method::HEAD, Method::OPTIONS] {
+            if (implied != Method::HEAD || methods.contains(&Method::GET))
+                && !methods.contains(&implied)
+            {
+                methods.push(implied);
+            }
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        methods
+    }
+
+    /// Whether a handler is registered for `method` and the route pattern `route`.
+    pub fn contains(&self, method: &Method, route: &str) -> bool {
+        self.tables
+            .get(method)
+            .and_then(|table| table.at(route).ok())
+            .is_some_and(|found| &*found.value.0 .0 == route)
+    }
+
+    /// Find the handler of `method` and `path`, `HEAD` falls back to the `GET` handler of the
+    /// paths without a `HEAD` handler of their own.
+    pub fn at(&self, method: &Method, path: &str) -> RouteMatch<'_, STRUCT, Request, Response> {
+        let lookup = |method: &Method| self.tables.get(method).and_then(|t| t.at(path).ok());
+        let found = match (lookup(method), method) {
+            (None, &Method::HEAD) => lookup(&Method::GET),
+            (found, _) => found,
+        };
+        if let Some(found) = found {
+            return RouteMatch::Found {
+                route: &found.value.0,
+                handler: &found.value.1,
+                params: found
+                    .params
+                    .iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect(),
+            };
+        }
+        match self.allowed_methods(path) {
+            allowed if allowed.is_empty() => RouteMatch::NotFound,
+            allowed if method == Method::OPTIONS => RouteMatch::Options(allowed),
+            allowed => RouteMatch::MethodNotAllowed(allowed),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RouterError {
+    /// the path is not a valid route pattern
+    InvalidPath { path: String, msg: String },
+    /// a handler is already registered for this method and path
+    Conflict {
+        method: Method,
+        path: String,
+        msg: String,
+    },
+}
+
+impl fmt::Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouterError::InvalidPath { path, msg } => write!(f, "invalid path {}: {}", path, msg),
+            RouterError::Conflict { method, path, msg } => {
+                write!(f, "conflicting route {} {}: {}", method, path, msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RouterError {}
+
+/// Handlers of one path by method, made with [`get`], [`post`], ... and chained, e.g.
+/// `get(list_cars).post(create_car)`.
+pub struct MethodRouter<STRUCT, Request, Response> {
+    handlers: Vec<(Method, BoxCloneHandler<STRUCT, Request, Response>)>,
+}
+
+impl<STRUCT, Request, Response> MethodRouter<STRUCT, Request, Response> {
+    pub fn new() -> Self {
+        MethodRouter { handlers: vec![] }
+    }
+
+    /// Route `method` to `f`, either a function taking extractors or the raw request, see
+    /// [`handler_fn`].
+    pub fn on<F, M>(mut self, method: Method, f: F) -> Self
+    where
+        HandlerFn<F, M>:
+            Handler<STRUCT, Request, Response = Response> + Clone + Send + Sync + 'static,
+        <HandlerFn<F, M> as Handler<STRUCT, Request>>::Future: Send + 'static,
+    {
+        self.handlers
+            .push((method, BoxCloneHandler::new(handler_fn(f))));
+        self
+    }
+
+    /// Wrap the handlers registered so far.
+    pub fn layer(mut self, layer: RouteLayer<STRUCT, Request, Response>) -> Self {
+        self.handlers = self
+            .handlers
+            .into_iter()
+            .map(|(method, handler)| (method, layer(handler)))
+            .collect();
+        self
+    }
+}
+
+impl<STRUCT, Request, Response> Default for MethodRouter<STRUCT, Request, Response> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+macro_rules! method_routing {
+    ($($name:ident => $method:ident,)*) => {
+        impl<STRUCT, Request, Response> MethodRouter<STRUCT, Request, Response> {
+            $(
+                #[allow(dead_code)]
+                pub fn $name<F, M>(self, f: F) -> Self
+                where
+                    HandlerFn<F, M>:
+                        Handler<STRUCT, Request, Response = Response> + Clone + Send + Sync + 'static,
+                    <HandlerFn<F, M> as Handler<STRUCT, Request>>::Future: Send + 'static,
+                {
+                    self.on(Method::$method, f)
+                }
+            )*
+        }
+
+        $(
+            #[allow(dead_code)]
+            pub fn $name<F, M, STRUCT, Request, Response>(f: F) -> MethodRouter<STRUCT, Request, Response>
+            where
+                HandlerFn<F, M>:
+                    Handler<STRUCT, Request, Response = Response> + Clone + Send + Sync + 'static,
+                <HandlerFn<F, M> as Handler<STRUCT, Request>>::Future: Send + 'static,
+            {
+                MethodRouter::new().on(Method::$method, f)
+            }
+        )*
+    };
+}
+
+method_routing! {
+    get => GET,
+    post => POST,
+    put => PUT,
+    patch => PATCH,
+    delete => DELETE,
+}
+
+struct Route<STRUCT, Request, Response> {
+    path: String,
+    method: Method,
+    handler: BoxCloneHandler<STRUCT, Request, Response>,
+}
+
+/// Builder of the route tables, route groups are routers of their own combined with
+/// [`Router::nest`] and [`Router::merge`].
+///
+/// Mistakes such as conflicting routes are reported by [`Router::build`].
+pub struct Router<STRUCT, Request, Response> {
+    routes: Vec<Route<STRUCT, Request, Response>>,
+    errors: Vec<RouterError>,
+}
+
+impl<STRUCT, Request, Response> Router<STRUCT, Request, Response> {
+    pub fn new() -> Self {
+        Router {
+            routes: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Register the handlers of `path`, a `matchit` pattern such as `/cars/{id}`.
+    pub fn route(mut self, path: &str, methods: MethodRouter<STRUCT, Request, Response>) -> Self {
+        if !path.starts_with('/') {
+            self.errors.push(RouterError::InvalidPath {
+                path: path.to_owned(),
+                msg: "path must start with /".to_owned(),
+            });
+            return self;
+        }
+        for (method, handler) in methods.handlers {
+            self.routes.push(Route {
+                path: path.to_owned(),
+                method,
+                handler,
+            });
+        }
+        self
+    }
+
+    /// Mount the routes of `router` under `prefix`, e.g. `/images` nested in `/ctl` is
+    /// served at `/ctl/images`.
+    pub fn nest(mut self, prefix: &str, router: Router<STRUCT, Request, Response>) -> Self {
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            self.errors.push(RouterError::InvalidPath {
+                path: prefix.to_owned(),
+                msg: "prefix must start with / and must not end with /".to_owned(),
+            });
+            return self;
+        }
+        for mut route in router.routes {
+            route.path = match route.path.as_str() {
+                "/" => prefix.to_owned(),
+                path => format!("{}{}", prefix, path),
+            };
+            self.routes.push(route);
+        }
+        self.errors.extend(router.errors);
+        self
+    }
+
+    /// Take over all the routes of `router`.
+    pub fn merge(mut self, router: Router<STRUCT, Request, Response>) -> Self {
+        self.routes.extend(router.routes);
+        self.errors.extend(router.errors);
+        self
+    }
+
+    /// Wrap the handlers of all the routes registered so far, routes added later are left out.
+    pub fn layer(mut self, layer: RouteLayer<STRUCT, Request, Response>) -> Self {
+        self.routes = self
+            .routes
+            .into_iter()
+            .map(|route| Route {
+                handler: layer(route.handler),
+                ..route
+            })
+            .collect();
+        self
+    }
+
+    /// Build the route tables, failing on the first invalid or conflicting route.
+    pub fn build(self) -> Result<Routes<STRUCT, Request, Response>, RouterError> {
+        if let Some(err) = self.errors.into_iter().next() {
+            return Err(err);
+        }
+        let mut tables: HashMap<Method, matchit::Router<_>> = HashMap::new();
+        for route in self.routes {
+            tables
+                .entry(route.method.clone())
+                .or_default()
+                .insert(
+                    route.path.as_str(),
+                    (MatchedPath(route.path.as_str().into()), route.handler),
+                )
+                .map_err(|e| match e {
+                    matchit::InsertError::Conflict { .. } => RouterError::Conflict {
+                        method: route.method,
+                        path: route.path.clone(),
+                        msg: e.to_string(),
+                    },
+                    _ => RouterError::InvalidPath {
+                        path: route.path.clone(),
+                        msg: e.to_string(),
+                    },
+                })?;
+        }
+        Ok(Routes { tables })
+    }
+}
+
+impl<STRUCT, Request, Response> Default for Router<STRUCT, Request, Response> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get, post, RouteMatch, Router, RouterError};
+    use crate::http::Context;
+    use hyper::Method;
+
+    type TestRouter = Router<(), (), &'static str>;
+
+    async fn ok(_: (), _: Context, _: ()) -> &'static str {
+        "ok"
+    }
+
+    #[test]
+    fn test_build_router() {
+        let images: TestRouter =
+            Router::new().route("/images", get(ok).post(ok).on(Method::HEAD, ok));
+        let routes = TestRouter::new()
+            .route("/cars/{id}", get(ok))
+            .nest("/ctl", images)
+            .build()
+            .expect("routes should not conflict");
+        assert!(matches!(
+            routes.at(&Method::POST, "/ctl/images"),
+            RouteMatch::Found { .. }
+        ));
+        assert!(matches!(
+            routes.at(&Method::HEAD, "/cars/1"),
+            RouteMatch::Found { route, params, .. }
+                if &*route.0 == "/cars/{id}" && params == [("id".to_owned(), "1".to_owned())]
+        ));
+        assert!(routes.contains(&Method::GET, "/cars/{id}"));
+        assert!(!routes.contains(&Method::GET, "/cars/1"));
+        assert!(matches!(
+            routes.at(&Method::DELETE, "/ctl/images"),
+            RouteMatch::MethodNotAllowed(allowed)
+                if allowed == [Method::GET, Method::HEAD, Method::OPTIONS, Method::POST]
+        ));
+        assert!(matches!(
+            routes.at(&Method::OPTIONS, "/cars/1"),
+            RouteMatch::Options(_)
+        ));
+        assert!(matches!(
+            routes.at(&Method::GET, "/trucks"),
+            RouteMatch::NotFound
+        ));
+
+        let conflict = TestRouter::new()
+            .route("/cars", post(ok))
+            .merge(TestRouter::new().route("/cars", post(ok)))
+            .build();
+        assert!(matches!(conflict, Err(RouterError::Conflict { .. })));
+
+        let invalid = TestRouter::new().nest("ctl/", TestRouter::new()).build();
+        assert!(matches!(invalid, Err(RouterError::InvalidPath { .. })));
+    }
+}
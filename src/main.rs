@@ -1,35 +1,52 @@
 #![deny(warnings)]
+mod cli;
+mod config;
 mod ctl;
 mod http;
 mod middleware;
+mod server;
 mod store;
+mod tls;
 
-use bytes::{Buf, Bytes};
-use http::into_response::IntoResponse;
+use bytes::Bytes;
+use cli::{Cli, Command};
+use config::Config;
+use http::problem::Problem;
+use http::{Extension, Headers, Json, Path, Query, State};
 use http_body_util::{BodyExt, Full};
-use hyper::body::Incoming;
+use hyper::body::{Body, Incoming};
 use hyper::header;
-use hyper::http::HeaderValue;
+use hyper::http::{HeaderMap, HeaderValue};
 use hyper::server::conn::http1;
 use hyper::{Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
-use serde::de::DeserializeOwned;
+use middleware::auth::{
+    BasicAuth, BearerAuth, ClientCertAuth, Htpasswd, JwtVerifier, Policy, Principal, RequireRoles,
+    StaticTokens, TokenVerifier,
+};
+use middleware::timeout::{Deadline, Limit, Timeouts};
 use serde::Serialize;
 use serde_json::json;
-use store::{Car, CarStore, MemCarStore, SQLiteCarStore, StoreError};
+use store::{
+    AsyncCarStore, Car, CarPatch, CarQuery, CarSort, Cursor, MemCarStore, StoreError,
+    DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT,
+};
 use tokio::net::TcpListener;
-use tokio::sync::watch;
+use tokio::signal;
 
 extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
-use std::collections::HashMap;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-const INTERNAL_SERVER_ERROR: &str = "Internal Server Error";
+const AUTH_REALM: &str = "rust-hands-on";
+/// Seconds a client is asked to wait before retrying a timed out request.
+const TIMEOUT_RETRY_AFTER_SECS: u64 = 1;
 
 type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
 
@@ -39,8 +56,11 @@ fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
         .boxed()
 }
 
-fn mk_err_response<T: Into<Bytes>>(code: StatusCode, body: T) -> Response<BoxBody> {
-    Response::builder().status(code).body(full(body)).unwrap()
+/// Error response with problem details, `detail` is left out when empty.
+fn mk_err_response<T: Into<String>>(code: StatusCode, detail: T) -> Response<BoxBody> {
+    Problem::new(code)
+        .with_detail(detail)
+        .into_response_with(full)
 }
 
 fn mk_json_response<T>(value: &T) -> Response<BoxBody>
@@ -52,25 +72,29 @@ where
             .header(header::CONTENT_TYPE, "application/json")
             .body(full(json))
             .unwrap(),
-        Err(_) => Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(full(INTERNAL_SERVER_ERROR))
-            .unwrap(),
+        Err(_) => mk_err_response(StatusCode::INTERNAL_SERVER_ERROR, ""),
     }
 }
 
-async fn decode_request_body<T: DeserializeOwned>(req: Request<Incoming>) -> Result<T, String> {
-    match req.collect().await {
-        Ok(bytes) => {
-            let buf = bytes.aggregate();
-            let mut de = serde_json::Deserializer::from_reader(buf.reader());
-            match T::deserialize(&mut de) {
-                Ok(body) => Ok(body),
-                Err(e) => Err(format!("failed to parse request body: {}", e)),
-            }
-        }
-        Err(e) => Err(e.to_string()),
+/// Strong entity tag of a car, changes whenever the car is written.
+fn car_etag(car: &Car) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", car.revision)).unwrap()
+}
+
+fn with_etag(mut resp: Response<BoxBody>, car: &Car) -> Response<BoxBody> {
+    resp.headers_mut().insert(header::ETAG, car_etag(car));
+    resp
+}
+
+/// Entity tags listed in a conditional header, `None` stands for `*`.
+fn conditional_etags(headers: &HeaderMap, name: header::HeaderName) -> Option<Option<Vec<String>>> {
+    let value = headers.get(name)?.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Some(None);
     }
+    Some(Some(
+        value.split(',').map(|tag| tag.trim().to_owned()).collect(),
+    ))
 }
 
 /*
@@ -92,253 +116,331 @@ fn ret_to_resp<T: serde::Serialize>(v: std::result::Result<T, String>) -> Respon
     }
 }
 
+#[derive(serde::Deserialize)]
+struct ListCarsParams {
+    brand: Option<String>,
+    model: Option<String>,
+    year_gte: Option<u16>,
+    year_lte: Option<u16>,
+    sort: Option<String>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct RequestPushImage {
+    image: String,
+}
+
 #[derive(Clone)]
 struct Svc {
     mux: std::sync::Arc<Router>,
-    car_store: std::sync::Arc<dyn CarStore + Send + Sync>,
+    car_store: std::sync::Arc<dyn AsyncCarStore + Send + Sync>,
+    timeouts: Timeouts,
 }
 
 impl Svc {
     fn store_err_to_resp(err: StoreError) -> Response<BoxBody> {
-        match err {
-            StoreError::NotFound(err_msg) => mk_err_response(StatusCode::NOT_FOUND, err_msg),
+        let problem = match err {
+            StoreError::NotFound(err_msg) => Problem::new(StatusCode::NOT_FOUND)
+                .with_type("/problems/car-not-found", "Car Not Found")
+                .with_detail(err_msg),
+            StoreError::PreconditionFailed(err_msg) => {
+                Problem::new(StatusCode::PRECONDITION_FAILED)
+                    .with_type("/problems/revision-mismatch", "Revision Mismatch")
+                    .with_detail(err_msg)
+            }
+            StoreError::Conflict(err_msg) => Problem::new(StatusCode::CONFLICT)
+                .with_type("/problems/car-conflict", "Car Conflict")
+                .with_detail(err_msg),
             StoreError::Internal(err_msg) => {
+                // never leak store internals to clients
                 error!("{}", err_msg);
-                mk_err_response(StatusCode::INTERNAL_SERVER_ERROR, INTERNAL_SERVER_ERROR)
+                Problem::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
+        };
+        problem.into_response_with(full)
+    }
+
+    /// The revision a write must apply to, according to the `If-Match` header.
+    ///
+    /// No header or `*` leaves the write unconditional. Weak tags never match, as RFC 9110 asks for
+    /// strong comparison.
+    async fn expected_revision(
+        &self,
+        id: u32,
+        headers: &HeaderMap,
+    ) -> Result<Option<u32>, Response<BoxBody>> {
+        let tags = match conditional_etags(headers, header::IF_MATCH) {
+            Some(Some(tags)) => tags,
+            _ => return Ok(None),
+        };
+        let revisions: Vec<u32> = tags
+            .iter()
+            .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+            .collect();
+        match revisions.as_slice() {
+            [] => Err(mk_err_response(
+                StatusCode::PRECONDITION_FAILED,
+                "If-Match does not match any revision",
+            )),
+            [revision] => Ok(Some(*revision)),
+            // several candidates, pin the write to the current revision if it is one of them
+            _ => match self.car_store.get_car(id).await {
+                Ok(car) if revisions.contains(&car.revision) => Ok(Some(car.revision)),
+                Ok(car) => Err(mk_err_response(
+                    StatusCode::PRECONDITION_FAILED,
+                    format!("car with id={} is at revision {}", id, car.revision),
+                )),
+                Err(e) => Err(Self::store_err_to_resp(e)),
+            },
         }
     }
 
-    async fn get_car_list(self, _: http::Context, _: Request<Incoming>) -> Response<BoxBody> {
-        match self.car_store.get_all_cars() {
-            Ok(cars) => mk_json_response(&cars),
+    /// GET /cars?brand=Ford&year_gte=2015&sort=-year&limit=50&cursor=...
+    async fn get_car_list(
+        State(svc): State<Svc>,
+        Query(params): Query<ListCarsParams>,
+    ) -> Response<BoxBody> {
+        let sort = match params.sort.as_deref().map(str::parse::<CarSort>) {
+            Some(Ok(sort)) => sort,
+            Some(Err(e)) => return mk_err_response(StatusCode::BAD_REQUEST, e),
+            None => CarSort::default(),
+        };
+        let cursor = match params.cursor.as_deref().map(|c| Cursor::decode(c, &sort)) {
+            Some(Ok(cursor)) => Some(cursor),
+            Some(Err(e)) => return mk_err_response(StatusCode::BAD_REQUEST, e),
+            None => None,
+        };
+        let query = CarQuery {
+            brand: params.brand,
+            model: params.model,
+            year_gte: params.year_gte,
+            year_lte: params.year_lte,
+            sort,
+            limit: params
+                .limit
+                .unwrap_or(DEFAULT_PAGE_LIMIT)
+                .clamp(1, MAX_PAGE_LIMIT),
+            cursor,
+        };
+
+        match svc.car_store.list_cars(query).await {
+            Ok(page) => mk_json_response(&page),
             Err(e) => Svc::store_err_to_resp(e),
         }
     }
 
-    async fn get_car_by_id(self, ctx: http::Context, _: Request<Incoming>) -> Response<BoxBody> {
-        match ctx.vars.get("id") {
-            Some(car_id) => {
-                let id: u32 = match car_id.trim().parse() {
-                    Ok(num) => num,
-                    Err(_) => {
-                        return mk_err_response(
-                            StatusCode::BAD_REQUEST,
-                            format!("invalid id={}, expect uint32 number", car_id),
-                        )
-                    }
+    async fn get_car_by_id(
+        State(svc): State<Svc>,
+        Path(id): Path<u32>,
+        Headers(headers): Headers,
+    ) -> Response<BoxBody> {
+        match svc.car_store.get_car(id).await {
+            Ok(car) => {
+                let etag = car_etag(&car);
+                let not_modified = match conditional_etags(&headers, header::IF_NONE_MATCH) {
+                    Some(None) => true,
+                    // weak comparison, W/"1" matches "1"
+                    Some(Some(tags)) => tags
+                        .iter()
+                        .any(|tag| tag.trim_start_matches("W/").as_bytes() == etag.as_bytes()),
+                    None => false,
                 };
-                match self.car_store.get_car(id) {
-                    Ok(car) => mk_json_response(&car),
-                    Err(store_err) => Self::store_err_to_resp(store_err),
+                if not_modified {
+                    let resp = Response::builder()
+                        .status(StatusCode::NOT_MODIFIED)
+                        .body(full(""))
+                        .unwrap();
+                    return with_etag(resp, &car);
                 }
+                with_etag(mk_json_response(&car), &car)
             }
-            None => {
-                return mk_err_response(StatusCode::BAD_REQUEST, format!("expect id in url path"))
-            }
+            Err(store_err) => Self::store_err_to_resp(store_err),
         }
     }
 
-    async fn create_car(self, _: http::Context, req: Request<Incoming>) -> Response<BoxBody> {
-        match decode_request_body::<Car>(req).await {
-            Ok(new_car) => {
-                if new_car.year <= 0 {
-                    return mk_err_response(
-                        StatusCode::BAD_REQUEST,
-                        "car year must be greater than 0",
-                    );
-                }
-                match self
-                    .car_store
-                    .create_car(new_car.brand, new_car.model, new_car.year)
-                {
-                    Ok(nid) => mk_json_response(&json!({ "id": nid }).to_string()),
-                    Err(e) => Svc::store_err_to_resp(e),
-                }
-            }
-            Err(e) => mk_err_response(StatusCode::BAD_REQUEST, format!("invalid json input:{e}")),
+    async fn create_car(State(svc): State<Svc>, Json(mut new_car): Json<Car>) -> Response<BoxBody> {
+        if let Err(e) = new_car.validate() {
+            return mk_err_response(StatusCode::BAD_REQUEST, e);
+        }
+        match svc.car_store.create_car(new_car).await {
+            Ok(nid) => mk_json_response(&json!({ "id": nid }).to_string()),
+            Err(e) => Svc::store_err_to_resp(e),
         }
     }
 
-    async fn update_car(self, ctx: http::Context, req: Request<Incoming>) -> Response<BoxBody> {
-        let car_id = match ctx.vars.get("id") {
-            Some(car_id) => match car_id.trim().parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => {
-                    return mk_err_response(
-                        StatusCode::BAD_REQUEST,
-                        format!("invalid id={}, expect uint32 number", car_id),
-                    )
-                }
-            },
-            None => {
-                return mk_err_response(StatusCode::BAD_REQUEST, format!("expect id in url path"))
-            }
+    async fn update_car(
+        State(svc): State<Svc>,
+        Path(car_id): Path<u32>,
+        Headers(headers): Headers,
+        Json(mut car): Json<Car>,
+    ) -> Response<BoxBody> {
+        let expected = match svc.expected_revision(car_id, &headers).await {
+            Ok(expected) => expected,
+            Err(resp) => return resp,
         };
+        car.id = car_id;
+        if let Err(e) = car.validate() {
+            return mk_err_response(StatusCode::BAD_REQUEST, e);
+        }
+        match svc.car_store.update_car(car, expected).await {
+            Ok(car) => with_etag(mk_json_response("{}"), &car),
+            Err(e) => Self::store_err_to_resp(e),
+        }
+    }
 
-        match decode_request_body::<Car>(req).await {
-            Ok(mut car) => {
-                car.id = car_id;
-                if car.year <= 0 {
-                    return mk_err_response(
-                        StatusCode::BAD_REQUEST,
-                        "car year must be greater than 0",
-                    );
-                };
-                match self.car_store.update_car(car) {
-                    Ok(()) => mk_json_response("{}"),
-                    Err(e) => Self::store_err_to_resp(e),
-                }
-            }
-            Err(e) => mk_err_response(StatusCode::BAD_REQUEST, format!("invalid json input:{e}")),
+    /// PATCH /cars/{id} with a JSON Merge Patch, or a JSON Patch if sent as `application/json-patch+json`
+    async fn patch_car(
+        State(svc): State<Svc>,
+        Path(car_id): Path<u32>,
+        Headers(headers): Headers,
+        Json(doc): Json<serde_json::Value>,
+    ) -> Response<BoxBody> {
+        let expected = match svc.expected_revision(car_id, &headers).await {
+            Ok(expected) => expected,
+            Err(resp) => return resp,
+        };
+        let is_json_patch = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json-patch+json"));
+        let patch = match is_json_patch {
+            true => CarPatch::from_json_patch(&doc),
+            false => CarPatch::from_merge_patch(&doc),
+        };
+        match patch {
+            Ok(patch) => match svc.car_store.patch_car(car_id, patch, expected).await {
+                Ok(car) => with_etag(mk_json_response(&car), &car),
+                Err(e) => Self::store_err_to_resp(e),
+            },
+            Err(e) => mk_err_response(StatusCode::BAD_REQUEST, format!("invalid patch: {e}")),
         }
     }
 
-    async fn delete_car(self, ctx: http::Context, _: Request<Incoming>) -> Response<BoxBody> {
-        match ctx.vars.get("id") {
-            Some(car_id) => {
-                let id: u32 = match car_id.trim().parse() {
-                    Ok(num) => num,
-                    Err(_) => {
-                        return mk_err_response(
-                            StatusCode::BAD_REQUEST,
-                            format!("invalid id={}, expect uint32 number", car_id),
-                        )
-                    }
-                };
-                match self.car_store.delete_car(id) {
-                    Ok(()) => mk_json_response("{}"),
-                    Err(e) => Self::store_err_to_resp(e),
-                }
-            }
-            None => {
-                return mk_err_response(StatusCode::BAD_REQUEST, format!("expect id in url path"))
-            }
+    async fn delete_car(
+        State(svc): State<Svc>,
+        Path(id): Path<u32>,
+        Headers(headers): Headers,
+    ) -> Response<BoxBody> {
+        let expected = match svc.expected_revision(id, &headers).await {
+            Ok(expected) => expected,
+            Err(resp) => return resp,
+        };
+        match svc.car_store.delete_car(id, expected).await {
+            Ok(()) => mk_json_response("{}"),
+            Err(e) => Self::store_err_to_resp(e),
         }
     }
 
-    async fn delete_all_cars(self, _: http::Context, _: Request<Incoming>) -> Response<BoxBody> {
-        match self.car_store.delete_all_cars() {
+    async fn delete_all_cars(State(svc): State<Svc>) -> Response<BoxBody> {
+        match svc.car_store.delete_all_cars().await {
             Ok(()) => mk_json_response("{}"),
             Err(e) => Self::store_err_to_resp(e),
         }
     }
 
-    fn list_images(
-        self,
-        _: http::Context,
-        _: Request<Incoming>,
-    ) -> impl Future<Output = Response<BoxBody>> {
-        async { ret_to_resp(ctl::list_images()) }
+    /// Requests timed out so far, by route.
+    async fn list_timeouts(State(svc): State<Svc>) -> Response<BoxBody> {
+        mk_json_response(&svc.timeouts.counts())
     }
 
-    fn push_image(
-        self,
-        _: http::Context,
-        r: Request<Incoming>,
-    ) -> impl Future<Output = Response<BoxBody>> {
-        async {
-            #[derive(serde::Deserialize)]
-            struct RequestPushImage {
-                image: String,
-            }
-            match decode_request_body::<RequestPushImage>(r).await {
-                Ok(img) => ret_to_resp(ctl::push_image(img.image)),
-                Err(e) => {
-                    mk_err_response(StatusCode::BAD_REQUEST, format!("invalid json input:{e}"))
-                }
-            }
-        }
+    async fn list_images() -> Response<BoxBody> {
+        ret_to_resp(ctl::list_images().await)
     }
 
-    async fn sleep(self, ctx: http::Context, _: Request<Incoming>) -> Response<BoxBody> {
-        let second = match ctx.vars.get("duration") {
-            Some(sec_str) => match sec_str.trim().parse() {
-                Ok(num) => num,
-                Err(_) => 1,
-            },
-            None => {
-                return mk_err_response(
-                    StatusCode::BAD_REQUEST,
-                    format!("expect second pamameter in url path"),
-                )
-            }
-        };
-        tokio::time::sleep(std::time::Duration::from_millis(second)).await;
+    async fn push_image(
+        Extension(principal): Extension<Principal>,
+        Extension(deadline): Extension<Deadline>,
+        Json(img): Json<RequestPushImage>,
+    ) -> Response<BoxBody> {
+        info!(
+            "{} pushes image {} within {:?}",
+            principal.name,
+            img.image,
+            deadline.remaining()
+        );
+        ret_to_resp(ctl::push_image(img.image).await)
+    }
+
+    async fn sleep(Path(millis): Path<u64>) -> Response<BoxBody> {
+        tokio::time::sleep(std::time::Duration::from_millis(millis)).await;
         mk_json_response("{}")
     }
 
-    fn build_router() -> Router {
-        fn add_route(
-            mux: &mut HashMap<Method, matchit::Router<HandlerFn>>,
-            path: &str,
-            methed: Method,
-            handler: http::BoxCloneHandler<Svc, Request<Incoming>, Response<BoxBody>>,
-        ) -> () {
-            mux.entry(methed)
-                .or_default()
-                .insert(path, handler.into())
-                .unwrap();
-        }
+    /// Routes of the server, handlers run within `timeout` unless a route sets its own.
+    ///
+    /// All of them require authentication, but reading cars if `auth` lets anonymous requests
+    /// do so, and are subject to the policy of `auth`.
+    fn build_router(
+        timeout: Duration,
+        ctl_timeout: Duration,
+        auth: &Auth,
+        timeouts: &Timeouts,
+    ) -> Result<Router, http::RouterError> {
+        SvcRouter::new()
+            .merge(auth.guard(
+                Svc::car_read_routes().layer(timeout_layer(timeout, timeouts)),
+                auth.anonymous_reads,
+            ))
+            .merge(auth.guard(
+                Svc::car_write_routes().layer(timeout_layer(timeout, timeouts)),
+                false,
+            ))
+            .nest(
+                "/ctl",
+                auth.guard(Svc::ctl_routes(timeout, ctl_timeout, timeouts), false),
+            )
+            .nest(
+                "/test",
+                auth.guard(
+                    Svc::test_routes().layer(timeout_layer(timeout, timeouts)),
+                    false,
+                ),
+            )
+            .build()
+    }
 
-        let mut mux: HashMap<Method, matchit::Router<HandlerFn>> = Router::new();
-        add_route(
-            &mut mux,
-            "/cars",
-            Method::POST,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::create_car)),
-        );
-        add_route(
-            &mut mux,
-            "/cars/:id",
-            Method::PUT,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::update_car)),
-        );
-        add_route(
-            &mut mux,
-            "/cars",
-            Method::GET,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::get_car_list)),
-        );
-        add_route(
-            &mut mux,
-            "/cars/:id",
-            Method::GET,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::get_car_by_id)),
-        );
-        add_route(
-            &mut mux,
-            "/cars",
-            Method::DELETE,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::delete_all_cars)),
-        );
-        add_route(
-            &mut mux,
-            "/cars/:id",
-            Method::DELETE,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::delete_car)),
-        );
+    fn car_read_routes() -> SvcRouter {
+        SvcRouter::new()
+            .route("/cars", http::get(Svc::get_car_list))
+            .route("/cars/{id}", http::get(Svc::get_car_by_id))
+    }
 
-        add_route(
-            &mut mux,
-            "/ctl/images",
-            Method::GET,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::list_images)),
-        );
-        add_route(
-            &mut mux,
-            "/ctl/images",
-            Method::POST,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::push_image)),
-        );
+    fn car_write_routes() -> SvcRouter {
+        SvcRouter::new()
+            .route(
+                "/cars",
+                http::post(Svc::create_car).delete(Svc::delete_all_cars),
+            )
+            .route(
+                "/cars/{id}",
+                http::put(Svc::update_car)
+                    .patch(Svc::patch_car)
+                    .delete(Svc::delete_car),
+            )
+    }
 
-        add_route(
-            &mut mux,
-            "/test/sleep/:duration",
-            Method::GET,
-            http::BoxCloneHandler::new(http::handler_fn(Svc::sleep)),
-        );
-        return mux;
+    /// `/ctl/*` drives docker on the host, so unlike other routes it always requires the token.
+    fn ctl_routes(timeout: Duration, ctl_timeout: Duration, timeouts: &Timeouts) -> SvcRouter {
+        SvcRouter::new()
+            .route(
+                "/images",
+                http::get(Svc::list_images).layer(timeout_layer(timeout, timeouts)),
+            )
+            // pushing an image uploads its layers, which takes far longer than a query
+            .route(
+                "/images",
+                http::post(Svc::push_image).layer(timeout_layer(ctl_timeout, timeouts)),
+            )
+            .route(
+                "/timeouts",
+                http::get(Svc::list_timeouts).layer(timeout_layer(timeout, timeouts)),
+            )
+    }
+
+    fn test_routes() -> SvcRouter {
+        SvcRouter::new().route("/sleep/{duration}", http::get(Svc::sleep))
     }
 }
 
@@ -355,103 +457,138 @@ impl hyper::service::Service<Request<Incoming>> for Svc {
 #[tokio::main]
 async fn main() -> Result<(), tower::BoxError> {
     pretty_env_logger::init();
-    let addr = SocketAddr::from(([0, 0, 0, 0], 9100));
-    let listener = TcpListener::bind(addr).await.expect("failed to bind");
+    let cli = <Cli as clap::Parser>::parse();
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration:\n{}", e);
+            std::process::exit(2);
+        }
+    };
+    if cli.print_config {
+        print!("{}", toml::to_string(&config.redacted())?);
+        return Ok(());
+    }
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Migrate => Ok(cli::migrate(&config)?),
+        Command::Seed { file } => Ok(cli::seed(&config, &file).await?),
+        Command::Export { format, output } => {
+            Ok(cli::export(&config, format, output.as_deref()).await?)
+        }
+    }
+}
+
+async fn serve(config: Config) -> Result<(), tower::BoxError> {
+    let listener = TcpListener::bind(config.listen)
+        .await
+        .expect("failed to bind");
 
-    let carstore = match std::env::var("DB_TYPE") {
-        // Ok(dbtyp) => match dbtyp.as_str() {
-        //     "sqlite" => &SQLiteCarStore::new() as &dyn CarStore, // temporary value get dropped at the end of this statement
-        //     _ => &MemCarStore::init() as &dyn CarStore, // temporary value get dropped at the end of this statement
-        // },
-        // Err(_) => &MemCarStore::init() as &dyn CarStore, //temporary value get dropped at the end of this statement
-        Ok(dbtyp) => match dbtyp.as_str() {
-            "sqlite" => Box::new(SQLiteCarStore::new()) as Box<dyn CarStore + Send + Sync>,
-            _ => Box::new(MemCarStore::init()) as Box<dyn CarStore + Send + Sync>,
-        },
-        Err(_) => Box::new(MemCarStore::init()) as Box<dyn CarStore + Send + Sync>,
+    let carstore = cli::open_store(&config)?;
+    // static tokens and JWTs of the gateway, without either no token is accepted
+    let auth_config = &config.auth;
+    let mut verifiers: Vec<Arc<dyn TokenVerifier>> = vec![];
+    if !auth_config.tokens.is_empty() {
+        verifiers.push(Arc::new(
+            auth_config.tokens.join("\n").parse::<StaticTokens>()?,
+        ));
+    }
+    if let Some(path) = &auth_config.token_file {
+        verifiers.push(Arc::new(StaticTokens::load(path)?));
+    }
+    if let (Some(path), Some(issuer), Some(audience)) = (
+        &auth_config.jwks_file,
+        &auth_config.jwt_issuer,
+        &auth_config.jwt_audience,
+    ) {
+        verifiers.push(Arc::new(JwtVerifier::load(path, issuer, audience)?));
+    }
+    if verifiers.is_empty()
+        && auth_config.htpasswd_file.is_none()
+        && config.tls.client_ca_file.is_none()
+    {
+        warn!("no tokens, htpasswd file or client CA is configured, requests requiring authentication are rejected");
+    }
+    let verifier: Arc<dyn TokenVerifier> = Arc::new(verifiers);
+    let timeout_sec = config.timeouts.request;
+    let ctl_timeout_sec = config.timeouts.ctl;
+    // how long in-flight requests may take to finish at shutdown
+    let drain_timeout_sec = config.timeouts.drain;
+    let policy = Arc::new(match &auth_config.policy_file {
+        Some(path) => Policy::load(path)?,
+        None => Policy::default(),
+    });
+    let basic = match &auth_config.htpasswd_file {
+        Some(path) => Some(BasicAuth::new(
+            Arc::new(Htpasswd::load(path)?),
+            AUTH_REALM,
+            full,
+        )),
+        None => None,
+    };
+    let auth = Auth {
+        bearer: BearerAuth::new(verifier, AUTH_REALM, full),
+        basic,
+        policy: RequireRoles::new(policy.clone(), AUTH_REALM, full),
+        anonymous_reads: auth_config.anonymous_reads,
     };
+    let timeouts = Timeouts::default();
+    let mux = Svc::build_router(
+        Duration::from_secs(timeout_sec),
+        Duration::from_secs(ctl_timeout_sec),
+        &auth,
+        &timeouts,
+    )?;
+    check_policy(&policy, &mux)?;
     let svc = Svc {
         // the size for values of type `dyn store::CarStore + Send + Sync` cannot be known at compilation time
         //   the trait `Sized` is not implemented for `dyn store::CarStore + Send + Sync`
         // car_store: std::sync::Arc::new(*carstore),
         //
         car_store: std::sync::Arc::from(carstore),
-        mux: std::sync::Arc::new(Svc::build_router()),
+        mux: std::sync::Arc::new(mux),
+        timeouts,
     };
-
-    let timeout_sec = std::env::var("TIMEOUT")
-        .map(|t| t.parse::<u64>().expect("TIMEOUT in seconds"))
-        .unwrap_or(3);
-    let svc = middleware::timeout::Timeout::new(svc, std::time::Duration::from_secs(timeout_sec));
-    let svc = middleware::auth::AsyncRequireAuthorization::new(
-        svc,
-        |req: Request<Incoming>| async move {
-            if let Some(token) = check_auth(&req).await {
-                if token != "zenx" {
-                    return Err(mk_err_response(StatusCode::UNAUTHORIZED, ""));
-                }
-            }
-            Ok(req)
-        },
-    );
-    let svc = middleware::util::MapResponse::new(svc, |resp: Response<BoxBody>| {
+    let server_owner = HeaderValue::from_str(&config.server_owner)
+        .map_err(|e| format!("invalid server_owner: {}", e))?;
+    let svc = middleware::util::MapResponse::new(svc, move |resp: Response<BoxBody>| {
         let (mut parts, body) = resp.into_parts();
-        parts
-            .headers
-            .append("Server-Owner", HeaderValue::from_str("zenx").unwrap());
+        if !server_owner.is_empty() {
+            parts.headers.append("Server-Owner", server_owner);
+        }
         Response::from_parts(parts, body)
     });
     // this layer will map the hyper::Error returned from the previous into tower::BoxError.
     let svc = middleware::error_handling::HandleError::new(svc, handle_error);
     let svc = middleware::log::LogRequest::new(svc);
 
-    println!("Listening on http://{}", addr);
-    let (tx, mut rx) = watch::channel(false);
-
-    tokio::task::spawn(async move {
-        loop {
-            let svc = svc.clone();
-            tokio::select! {
-                res = listener.accept() => {
-                    let (stream, _) = res.expect("Failed to accept");
-
-                    // Use an adapter to access something implementing `tokio::io` traits as if they implement
-                    // `hyper::rt` IO traits.
-                    // fix: the trait `hyper::rt::Read` is not implemented for `tokio::net::TcpStream`
-                    let io = TokioIo::new(stream);
-
-                    let mut rx = rx.clone();
-                    tokio::task::spawn(async move {
-                        let mut conn = http1::Builder::new().serve_connection(io, svc);
-                        let mut conn = Pin::new(&mut conn);
-                        tokio::select! {
-                            res = &mut conn => {
-                                if let Err(err) = res {
-                                    println!("Error serving connection: {:?}", err);
-                                    return;
-                                }
-                            }
-                            _ = rx.changed() => {
-                                conn.graceful_shutdown();
-                            }
-                        }
-                    });
-                }
-                _ = rx.changed() => {
-                    break;
-                }
-            }
-        }
-    });
+    let tls = match (&config.tls.cert_file, &config.tls.key_file) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(&tls::TlsConfig {
+            cert: cert.clone(),
+            key: key.clone(),
+            client_ca: config.tls.client_ca_file.clone(),
+            require_client_cert: config.tls.require_client_cert,
+        })?),
+        _ => None,
+    };
 
-    match tokio::signal::ctrl_c().await {
-        Ok(()) => {
-            let _ = tx.send(true);
-            // normally we should return when all in-flight connections are finished or a timeout occurred,
-            // but currently in-flight connections have not been recorded (don't know how to achieve it for now...).
-            Ok(())
-        }
-        Err(err) => Err(format!("Unable to listen for shutdown signal: {}", err).into()),
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    println!("Listening on {}://{}", scheme, config.listen);
+    let server = server::Server {
+        conn_builder: server::conn_builder(&config.http2),
+        tls,
+        drain: Duration::from_secs(drain_timeout_sec),
+    };
+    server.run(listener, svc, shutdown_signal()).await
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM, as sent by `docker stop` and Kubernetes.
+async fn shutdown_signal() -> Result<&'static str, String> {
+    let err = |e: std::io::Error| format!("Unable to listen for shutdown signal: {}", e);
+    let mut term = signal::unix::signal(signal::unix::SignalKind::terminate()).map_err(err)?;
+    tokio::select! {
+        res = signal::ctrl_c() => res.map(|()| "SIGINT").map_err(err),
+        _ = term.recv() => Ok("SIGTERM"),
     }
 }
 
@@ -463,9 +600,20 @@ async fn x_main() -> Result<(), tower::BoxError> {
     let listener = TcpListener::bind(addr).await.expect("failed to bind");
     let svc = Svc {
         car_store: std::sync::Arc::from(
-            Box::new(MemCarStore::init()) as Box<dyn CarStore + Send + Sync>
+            Box::new(MemCarStore::init()) as Box<dyn AsyncCarStore + Send + Sync>
         ),
-        mux: std::sync::Arc::new(Svc::build_router()),
+        mux: std::sync::Arc::new(Svc::build_router(
+            Duration::from_secs(3),
+            Duration::from_secs(60),
+            &Auth {
+                bearer: BearerAuth::new(Arc::new(StaticTokens::default()), AUTH_REALM, full),
+                basic: None,
+                policy: RequireRoles::new(Arc::new(Policy::default()), AUTH_REALM, full),
+                anonymous_reads: true,
+            },
+            &Timeouts::default(),
+        )?),
+        timeouts: Timeouts::default(),
     };
     // let mux = std::sync::Arc::new(Svc::build_router());
 
@@ -500,59 +648,461 @@ async fn x_main() -> Result<(), tower::BoxError> {
     }
 }
 
-type HandlerFn = std::sync::Mutex<http::BoxCloneHandler<Svc, Request<Incoming>, Response<BoxBody>>>;
-type Router = HashMap<Method, matchit::Router<HandlerFn>>;
+type SvcLayer = http::RouteLayer<Svc, Request<Incoming>, Response<BoxBody>>;
+type SvcRouter = http::Router<Svc, Request<Incoming>, Response<BoxBody>>;
+type Router = http::Routes<Svc, Request<Incoming>, Response<BoxBody>>;
 
-fn route(
+async fn route(
     mux: std::sync::Arc<Router>,
     s: Svc,
-    req: Request<Incoming>,
-) -> impl Future<Output = Result<Response<BoxBody>, tower::BoxError>> + Send {
-    async move {
-        // find the subrouter for this request method
-        let router = match mux.get(req.method()) {
-            Some(router) => router,
-            None => return Ok(mk_err_response(StatusCode::METHOD_NOT_ALLOWED, "")),
-        };
+    mut req: Request<Incoming>,
+) -> Result<Response<BoxBody>, tower::BoxError> {
+    let path = req.uri().path().to_owned();
+    let (handler, params) = match mux.at(req.method(), &path) {
+        http::RouteMatch::Found {
+            route,
+            handler,
+            params,
+        } => {
+            // for the route layers, such as the access policy
+            req.extensions_mut().insert(route.clone());
+            (handler, params)
+        }
+        http::RouteMatch::Options(allowed) => {
+            let mut resp = Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(full(""))
+                .unwrap();
+            resp.headers_mut()
+                .insert(header::ALLOW, allow_header(&allowed));
+            return Ok(resp);
+        }
+        http::RouteMatch::MethodNotAllowed(allowed) => {
+            let mut resp = Problem::new(StatusCode::METHOD_NOT_ALLOWED)
+                .with_detail(format!("{} is not routed for {}", req.method(), path))
+                .with_instance(path)
+                .into_response_with(full);
+            resp.headers_mut()
+                .insert(header::ALLOW, allow_header(&allowed));
+            return Ok(resp);
+        }
+        // if we there is no matching service, call the 404 handler
+        http::RouteMatch::NotFound => {
+            return Ok(Problem::new(StatusCode::NOT_FOUND)
+                .with_instance(path)
+                .into_response_with(full))
+        }
+    };
 
-        match router.at(req.uri().path()) {
-            Ok(found) => {
-                let mut ctx = http::Context {
-                    vars: HashMap::new(),
-                };
-                for p in found.params.iter() {
-                    ctx.vars.insert(p.0.to_owned(), p.1.to_owned());
-                }
-                // lock the service for a very short time, just to clone the service
-                let res = {
-                    let mut ha = found.value.lock().unwrap().clone();
-                    http::Handler::call(&mut ha, s, ctx, req).await
-                };
-                Ok(res)
+    let ctx = http::Context::new(params, &req);
+    let is_head = req.method() == Method::HEAD;
+    // the routes are shared by all connections, each request calls its own clone
+    let mut ha = handler.clone();
+    let res = http::Handler::call(&mut ha, s, ctx, req).await;
+    if is_head {
+        // same headers as GET, including the length of the body left out
+        let (mut parts, body) = res.into_parts();
+        if let Some(len) = body.size_hint().exact() {
+            parts.headers.insert(header::CONTENT_LENGTH, len.into());
+        }
+        return Ok(Response::from_parts(parts, full("")));
+    }
+    Ok(res)
+}
+
+async fn handle_error(error: middleware::timeout::BoxError) -> Response<BoxBody> {
+    error_response(error)
+}
+
+fn error_response(error: middleware::timeout::BoxError) -> Response<BoxBody> {
+    if let Some(error) = error.downcast_ref::<middleware::timeout::TimeoutError>() {
+        return timeout_response(error.limit());
+    }
+
+    error!("unhandled internal error: {}", error);
+    Problem::new(StatusCode::INTERNAL_SERVER_ERROR).into_response_with(full)
+}
+
+/// `503` with `Retry-After` when the server ran out of time, unlike `408` which blames a slow
+/// client, and the request may well succeed once the load is gone.
+///
+/// `504` when the deadline of the client came first: retrying with the same deadline would
+/// likely fail again, so no `Retry-After`.
+fn timeout_response(limit: Limit) -> Response<BoxBody> {
+    if limit == Limit::Client {
+        return Problem::new(StatusCode::GATEWAY_TIMEOUT)
+            .with_type("/problems/deadline-exceeded", "Deadline Exceeded")
+            .with_detail(format!(
+                "no response within the {} of the request",
+                middleware::timeout::REQUEST_TIMEOUT_HEADER
+            ))
+            .into_response_with(full);
+    }
+    let mut resp = Problem::new(StatusCode::SERVICE_UNAVAILABLE)
+        .with_type("/problems/timeout", "Request Timed Out")
+        .with_detail("the server did not respond in time")
+        .into_response_with(full);
+    resp.headers_mut()
+        .insert(header::RETRY_AFTER, TIMEOUT_RETRY_AFTER_SECS.into());
+    resp
+}
+
+fn allow_header(methods: &[Method]) -> HeaderValue {
+    let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    HeaderValue::from_str(&methods.join(", ")).unwrap()
+}
+
+/// Answer 503 once the handler of a route takes longer than `timeout`, counted in `timeouts`,
+/// or 504 once it takes longer than the client asked for.
+fn timeout_layer(timeout: Duration, timeouts: &Timeouts) -> SvcLayer {
+    let timeouts = timeouts.clone();
+    http::layer_fn(move |inner| {
+        middleware::util::MapResult::new(
+            middleware::timeout::Timeout::new(inner, timeout).counting(timeouts.clone()),
+            |res: Result<Response<BoxBody>, middleware::timeout::BoxError>| {
+                Ok::<_, std::convert::Infallible>(res.unwrap_or_else(error_response))
+            },
+        )
+    })
+}
+
+/// How requests are authenticated and authorized, see [`Auth::guard`].
+#[derive(Clone)]
+struct Auth {
+    bearer: BearerAuth<BoxBody>,
+    /// users of the htpasswd file, if any, for internal tooling
+    basic: Option<BasicAuth<BoxBody>>,
+    policy: RequireRoles<BoxBody>,
+    /// let requests without credentials read cars, see `auth.anonymous_reads`
+    anonymous_reads: bool,
+}
+
+impl Auth {
+    /// Enforce the policy on the routes of `router`, authenticated with a TLS client
+    /// certificate, basic credentials or a bearer token, one of which is required unless
+    /// `optional`; a `401` challenges the client to each scheme of an HTTP header.
+    fn guard(&self, router: SvcRouter, optional: bool) -> SvcRouter {
+        let (mut policy, mut bearer) = (self.policy.clone(), self.bearer.clone());
+        if let Some(basic) = &self.basic {
+            policy = policy.with_challenge(basic.challenge());
+            bearer = bearer.with_challenge(basic.challenge());
+        }
+        let bearer = match optional {
+            true => bearer.optional(),
+            false => bearer,
+        };
+        let router = router
+            .layer(http::layer_fn(move |inner| policy.layer(inner)))
+            .layer(http::layer_fn(move |inner| bearer.layer(inner)));
+        // the outer layers, a principal authenticated by them is taken by the bearer layer
+        let router = match self.basic.clone() {
+            Some(basic) => {
+                let basic = basic.optional();
+                router.layer(http::layer_fn(move |inner| basic.layer(inner)))
             }
-            // if we there is no matching service, call the 404 handler
-            Err(_) => Ok(mk_err_response(StatusCode::NOT_FOUND, "")),
+            None => router,
+        };
+        router.layer(http::layer_fn(|inner| {
+            ClientCertAuth::<BoxBody>::new().layer(inner)
+        }))
+    }
+}
+
+/// A rule of a route which does not exist is most likely a typo, which would leave the
+/// intended route unprotected.
+fn check_policy(policy: &Policy, mux: &Router) -> Result<(), String> {
+    let methods = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ];
+    for rule in policy.rules() {
+        let found = match &rule.method {
+            Some(method) => mux.contains(method, &rule.route),
+            None => methods
+                .iter()
+                .any(|method| mux.contains(method, &rule.route)),
+        };
+        if !found {
+            return Err(format!(
+                "policy rule of an unknown route: {} {}",
+                rule.method.as_ref().map_or("*", |m| m.as_str()),
+                rule.route
+            ));
         }
     }
+    Ok(())
 }
 
-async fn handle_error(error: middleware::timeout::BoxError) -> impl IntoResponse {
-    if error.is::<middleware::timeout::TimeoutError>() {
-        return (
-            StatusCode::REQUEST_TIMEOUT,
-            String::from("request timed out"),
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::oneshot;
+
+    const ADMIN: &str = "Bearer admin-token";
+
+    fn test_auth() -> Auth {
+        let tokens: StaticTokens = "admin-token zenx admin".parse().unwrap();
+        Auth {
+            bearer: BearerAuth::new(Arc::new(tokens), AUTH_REALM, full),
+            basic: None,
+            policy: RequireRoles::new(Arc::new(Policy::default()), AUTH_REALM, full),
+            anonymous_reads: false,
+        }
+    }
+
+    fn test_svc(mux: Router, timeouts: Timeouts) -> Svc {
+        Svc {
+            mux: Arc::new(mux),
+            car_store: Arc::new(MemCarStore::init()),
+            timeouts,
+        }
+    }
+
+    /// The routes of the server, handlers timing out after `timeout`.
+    fn server(timeout: Duration) -> Svc {
+        let timeouts = Timeouts::default();
+        let mux =
+            Svc::build_router(timeout, Duration::from_secs(60), &test_auth(), &timeouts).unwrap();
+        test_svc(mux, timeouts)
+    }
+
+    async fn send(
+        svc: &Svc,
+        req: hyper::http::request::Builder,
+        body: &'static str,
+    ) -> Response<Bytes> {
+        oneshot(svc.clone(), req.body(Full::new(Bytes::from(body))).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_route_layers() {
+        let svc = server(Duration::from_millis(100));
+        let resp = send(
+            &svc,
+            Request::get("/cars/1").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // `/test` and `/ctl` require a token of their own
+        for uri in ["/test/sleep/0", "/ctl/timeouts"] {
+            let resp = send(&svc, Request::get(uri), "").await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+            assert!(resp.headers().contains_key(header::WWW_AUTHENTICATE));
+        }
+        let resp = send(
+            &svc,
+            Request::get("/test/sleep/0").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // the timeout layer of the route, not of the whole server
+        let resp = send(
+            &svc,
+            Request::get("/test/sleep/1000").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        let svc = server(Duration::from_millis(50));
+        let admin = |req: hyper::http::request::Builder| req.header(header::AUTHORIZATION, ADMIN);
+        for _ in 0..2 {
+            let resp = send(&svc, admin(Request::get("/test/sleep/1000")), "").await;
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(resp.headers()[header::RETRY_AFTER], "1");
+            let problem: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(problem["type"], "/problems/timeout");
+        }
+        let resp = send(&svc, admin(Request::get("/test/sleep/0")), "").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send(&svc, admin(Request::get("/test/nap/1000")), "").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // out of the client's own time: not the server's fault, not counted
+        let early = Request::get("/test/sleep/1000")
+            .header(middleware::timeout::REQUEST_TIMEOUT_HEADER, "10");
+        let resp = send(&svc, admin(early), "").await;
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(!resp.headers().contains_key(header::RETRY_AFTER));
+        let problem: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(problem["type"], "/problems/deadline-exceeded");
+
+        // by route pattern, unrouted requests are never counted
+        let resp = send(&svc, admin(Request::get("/ctl/timeouts")), "").await;
+        let counts: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(counts, json!({ "/test/sleep/{duration}": 2 }));
+    }
+
+    fn challenges(resp: &Response<Bytes>) -> Vec<&str> {
+        resp.headers()
+            .get_all(header::WWW_AUTHENTICATE)
+            .iter()
+            .map(|v| v.to_str().unwrap().split(' ').next().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_car_auth() {
+        let svc = server(Duration::from_secs(3));
+        for req in [Request::get("/cars"), Request::delete("/cars/1")] {
+            let resp = send(&svc, req, "").await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(challenges(&resp), ["Bearer"]);
+        }
+
+        // every scheme of a header is offered
+        let path = std::env::temp_dir().join(format!("htpasswd-main-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            format!("ci:{}\n", bcrypt::hash("s3cr3t", 4).unwrap()),
+        )
+        .unwrap();
+        let mut auth = test_auth();
+        auth.basic = Some(BasicAuth::new(
+            Arc::new(Htpasswd::load(&path).unwrap()),
+            AUTH_REALM,
+            full,
+        ));
+        std::fs::remove_file(&path).unwrap();
+        let timeouts = Timeouts::default();
+        let mux = Svc::build_router(
+            Duration::from_secs(3),
+            Duration::from_secs(60),
+            &auth,
+            &timeouts,
+        )
+        .unwrap();
+        let svc = test_svc(mux, timeouts.clone());
+        let resp = send(&svc, Request::get("/cars"), "").await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(challenges(&resp), ["Bearer", "Basic"]);
+        // base64 of ci:s3cr3t
+        let basic = "Basic Y2k6czNjcjN0";
+        let resp = send(
+            &svc,
+            Request::get("/cars").header(header::AUTHORIZATION, basic),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // reads only
+        auth.anonymous_reads = true;
+        let mux = Svc::build_router(
+            Duration::from_secs(3),
+            Duration::from_secs(60),
+            &auth,
+            &timeouts,
+        )
+        .unwrap();
+        let svc = test_svc(mux, timeouts);
+        let resp = send(&svc, Request::get("/cars/1"), "").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send(&svc, Request::delete("/cars/1"), "").await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn allow(resp: &Response<Bytes>) -> &str {
+        resp.headers()[header::ALLOW].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_method_routing() {
+        let svc = server(Duration::from_secs(3));
+        let resp = send(
+            &svc,
+            Request::patch("/cars").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow(&resp), "DELETE, GET, HEAD, OPTIONS, POST");
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            http::problem::PROBLEM_CONTENT_TYPE
         );
+
+        let resp = send(&svc, Request::options("/cars/1"), "").await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(allow(&resp), "DELETE, GET, HEAD, OPTIONS, PATCH, PUT");
+
+        let get = send(
+            &svc,
+            Request::get("/cars/1").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        let head = send(
+            &svc,
+            Request::head("/cars/1").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(head.status(), StatusCode::OK);
+        assert!(head.body().is_empty());
+        assert_eq!(
+            head.headers()[header::CONTENT_LENGTH],
+            get.body().len().to_string()
+        );
+        assert_eq!(head.headers()[header::ETAG], get.headers()[header::ETAG]);
+
+        let resp = send(&svc, Request::get("/trucks"), "").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(!resp.headers().contains_key(header::ALLOW));
     }
 
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        String::from(format!("Unhandled internal error: {}", error)),
-    )
-}
+    async fn whoami(
+        Extension(principal): Extension<Principal>,
+        Extension(deadline): Extension<Deadline>,
+    ) -> Response<BoxBody> {
+        mk_json_response(&json!({
+            "name": principal.name,
+            "remaining_ms": deadline.remaining().as_millis() as u64,
+        }))
+    }
 
-async fn check_auth<B>(request: &Request<B>) -> Option<String> {
-    request
-        .headers()
-        .get("Bearer")
-        .map(|v| v.to_str().unwrap_or_default().to_string())
+    async fn no_deadline(Extension(deadline): Extension<Deadline>) -> Response<BoxBody> {
+        mk_json_response(&deadline.remaining().as_millis().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_context_extensions() {
+        let timeouts = Timeouts::default();
+        let routes = SvcRouter::new()
+            .route("/whoami", http::get(whoami))
+            .layer(timeout_layer(Duration::from_secs(2), &timeouts))
+            .route("/deadline", http::get(no_deadline));
+        let mux = test_auth().guard(routes, false).build().unwrap();
+        let svc = test_svc(mux, timeouts);
+
+        let resp = send(
+            &svc,
+            Request::get("/whoami").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["name"], "zenx");
+        let remaining = body["remaining_ms"].as_u64().unwrap();
+        assert!(remaining > 0 && remaining <= 2000, "{}", remaining);
+
+        // a handler reading a value no middleware inserted is a bug of the stack
+        let resp = send(
+            &svc,
+            Request::get("/deadline").header(header::AUTHORIZATION, ADMIN),
+            "",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
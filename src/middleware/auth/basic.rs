@@ -0,0 +1,368 @@
+use super::bearer::{constant_time_eq, split_roles};
+use super::{AsyncAuthorizeRequest, AsyncRequireAuthorization, Principal};
+use crate::http::problem::Problem;
+use argon2::{password_hash::PasswordHash, Argon2, PasswordVerifier};
+use base64::Engine;
+use bytes::Bytes;
+use hyper::{header, http::HeaderValue, Request, Response, StatusCode};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the htpasswd file is checked for changes, at most.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+struct User {
+    name: String,
+    hash: String,
+    roles: Vec<String>,
+}
+
+struct Loaded {
+    modified: Option<SystemTime>,
+    checked: Instant,
+    users: Vec<User>,
+}
+
+/// Users of an htpasswd file, one `<user>:<bcrypt or argon2 hash>[:<role>,...]` per line,
+/// reloaded when the file changes.
+///
+/// Hashes are made with e.g. `htpasswd -nbB <user> <password>`, blank lines and lines
+/// starting with `#` are skipped.
+pub struct Htpasswd {
+    path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+impl Htpasswd {
+    pub fn load(path: impl AsRef<Path>) -> Result<Htpasswd, String> {
+        let path = path.as_ref();
+        let (modified, users) = Self::read(path)?;
+        Ok(Htpasswd {
+            path: path.to_owned(),
+            loaded: RwLock::new(Loaded {
+                modified,
+                checked: Instant::now(),
+                users,
+            }),
+        })
+    }
+
+    fn read(path: &Path) -> Result<(Option<SystemTime>, Vec<User>), String> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let users = parse(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok((modified, users))
+    }
+
+    /// Read the file again if it has changed, a broken file leaves the users loaded before.
+    fn reload_if_changed(&self) {
+        {
+            let loaded = self.loaded.read().unwrap();
+            if loaded.checked.elapsed() < RELOAD_INTERVAL {
+                return;
+            }
+        }
+        let mut loaded = self.loaded.write().unwrap();
+        loaded.checked = Instant::now();
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified == loaded.modified {
+            return;
+        }
+        match Self::read(&self.path) {
+            Ok((modified, users)) => {
+                info!("reloaded {}, {} users", self.path.display(), users.len());
+                loaded.modified = modified;
+                loaded.users = users;
+            }
+            Err(e) => error!("failed to reload, keeping the users loaded before: {}", e),
+        }
+    }
+
+    /// Check the password of `user`, this blocks for the tens of milliseconds hashing takes
+    /// on purpose.
+    pub fn verify(&self, user: &str, password: &str) -> Option<Principal> {
+        self.reload_if_changed();
+        let loaded = self.loaded.read().unwrap();
+        // compare with every user, and check an unknown user against the hash of the first
+        // one, whatever its scheme and cost, so that the time taken tells nothing about who
+        // exists
+        let mut found = None;
+        for known in &loaded.users {
+            if constant_time_eq(known.name.as_bytes(), user.as_bytes()) {
+                found = Some(known);
+            }
+        }
+        let Some(known) = found else {
+            if let Some(first) = loaded.users.first() {
+                verify_hash(password, &first.hash);
+            }
+            return None;
+        };
+        verify_hash(password, &known.hash).then(|| Principal {
+            name: known.name.clone(),
+            roles: known.roles.clone(),
+        })
+    }
+}
+
+fn parse(content: &str) -> Result<Vec<User>, String> {
+    let mut users = vec![];
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ':');
+        let (Some(name), Some(hash)) = (fields.next(), fields.next()) else {
+            return Err(format!(
+                "line {}: expect `<user>:<hash>[:<role>,...]`",
+                n + 1
+            ));
+        };
+        if !is_bcrypt(hash) && !hash.starts_with("$argon2") {
+            return Err(format!("line {}: expect a bcrypt or argon2 hash", n + 1));
+        }
+        users.push(User {
+            name: name.to_owned(),
+            hash: hash.to_owned(),
+            roles: split_roles(fields.next().unwrap_or_default()),
+        });
+    }
+    Ok(users)
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+/// Both libraries compare the hashes in constant time.
+fn verify_hash(password: &str, hash: &str) -> bool {
+    if is_bcrypt(hash) {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Authorize requests carrying `Authorization: Basic` credentials of the [`Htpasswd`] users,
+/// see [`AsyncRequireAuthorization`]; the [`Principal`] is inserted into the request
+/// extensions, unless an outer layer inserted one already, e.g. of a client certificate,
+/// which is kept.
+///
+/// Other requests are answered `401` with a `WWW-Authenticate` challenge (RFC 7617), unless
+/// it is [`BasicAuth::optional`].
+pub struct BasicAuth<ResBody> {
+    htpasswd: Arc<Htpasswd>,
+    realm: Arc<str>,
+    body: fn(Bytes) -> ResBody,
+    required: bool,
+}
+
+impl<ResBody> Clone for BasicAuth<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            htpasswd: self.htpasswd.clone(),
+            realm: self.realm.clone(),
+            body: self.body,
+            required: self.required,
+        }
+    }
+}
+
+impl<ResBody> BasicAuth<ResBody> {
+    pub fn new(htpasswd: Arc<Htpasswd>, realm: &str, body: fn(Bytes) -> ResBody) -> Self {
+        BasicAuth {
+            htpasswd,
+            realm: realm.into(),
+            body,
+            required: true,
+        }
+    }
+
+    /// Let requests without basic credentials through, e.g. to an inner [`super::BearerAuth`];
+    /// wrong credentials are still rejected.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    pub fn layer<S>(&self, inner: S) -> AsyncRequireAuthorization<S, Self> {
+        AsyncRequireAuthorization::new(inner, self.clone())
+    }
+
+    /// The `WWW-Authenticate` challenge of the realm.
+    pub fn challenge(&self) -> HeaderValue {
+        let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm);
+        HeaderValue::from_str(&challenge).unwrap_or_else(|_| HeaderValue::from_static("Basic"))
+    }
+
+    fn unauthorized(&self, detail: &str) -> Response<ResBody> {
+        let mut resp = Problem::new(StatusCode::UNAUTHORIZED)
+            .with_detail(detail)
+            .into_response_with(self.body);
+        resp.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, self.challenge());
+        resp
+    }
+}
+
+/// The user and password of `Authorization: Basic <base64 of user:password>`, `None` for
+/// another scheme, and `Some(Err)` for malformed credentials.
+fn basic_credentials<B>(req: &Request<B>) -> Option<Result<(String, String), &'static str>> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    Some(
+        decoded
+            .as_deref()
+            .and_then(|decoded| decoded.split_once(':'))
+            .map(|(user, password)| (user.to_owned(), password.to_owned()))
+            .ok_or("malformed basic credentials"),
+    )
+}
+
+impl<B, ResBody> AsyncAuthorizeRequest<B> for BasicAuth<ResBody>
+where
+    B: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type RequestBody = B;
+    type ResponseBody = ResBody;
+    type Future = BoxFuture<Result<Request<B>, Response<ResBody>>>;
+
+    fn authorize(&self, mut req: Request<B>) -> Self::Future {
+        // authenticated by an outer layer already, e.g. with a client certificate
+        if req.extensions().get::<Principal>().is_some() {
+            return Box::pin(std::future::ready(Ok(req)));
+        }
+        let (user, password) = match basic_credentials(&req) {
+            Some(Ok(credentials)) => credentials,
+            Some(Err(e)) => return Box::pin(std::future::ready(Err(self.unauthorized(e)))),
+            None if self.required => {
+                return Box::pin(std::future::ready(Err(self.unauthorized(""))))
+            }
+            None => return Box::pin(std::future::ready(Ok(req))),
+        };
+        let auth = self.clone();
+        Box::pin(async move {
+            // hashing would hold up the other requests of this worker thread
+            let htpasswd = auth.htpasswd.clone();
+            let principal = tokio::task::spawn_blocking(move || htpasswd.verify(&user, &password))
+                .await
+                .unwrap_or(None);
+            match principal {
+                Some(principal) => {
+                    req.extensions_mut().insert(principal);
+                    Ok(req)
+                }
+                None => Err(auth.unauthorized("wrong user or password")),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BasicAuth, Htpasswd};
+    use crate::middleware::auth::{AsyncAuthorizeRequest, Principal};
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    use bytes::Bytes;
+    use hyper::{header, Request, StatusCode};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_htpasswd() {
+        let path = std::env::temp_dir().join(format!("htpasswd-{}", std::process::id()));
+        let bcrypt = bcrypt::hash("s3cr3t", 4).unwrap();
+        let salt = SaltString::encode_b64(b"saltsaltsalt").unwrap();
+        let argon2 = argon2::Argon2::default()
+            .hash_password(b"hunter2", &salt)
+            .unwrap();
+        std::fs::write(
+            &path,
+            format!("# ops\nzenx:{}:admin,ops\nci:{}\n", bcrypt, argon2),
+        )
+        .unwrap();
+        let htpasswd = Htpasswd::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let principal = htpasswd.verify("zenx", "s3cr3t").unwrap();
+        assert_eq!(principal.roles, ["admin", "ops"]);
+        assert_eq!(htpasswd.verify("ci", "hunter2").unwrap().name, "ci");
+        assert!(htpasswd.verify("zenx", "wrong").is_none());
+        assert!(htpasswd.verify("nobody", "s3cr3t").is_none());
+        // as fast as a known user of the cheap bcrypt cost, not the default one
+        let start = Instant::now();
+        htpasswd.verify("zenx", "wrong");
+        let known = start.elapsed();
+        let start = Instant::now();
+        htpasswd.verify("nobody", "wrong");
+        let unknown = start.elapsed();
+        assert!(
+            unknown < known * 10 + Duration::from_millis(50),
+            "known {:?}, unknown {:?}",
+            known,
+            unknown
+        );
+        assert!(super::parse("ci:plain").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keep_principal() {
+        let path = std::env::temp_dir().join(format!("htpasswd-keep-{}", std::process::id()));
+        let hash = bcrypt::hash("s3cr3t", 4).unwrap();
+        std::fs::write(&path, format!("ci:{}:admin\n", hash)).unwrap();
+        let htpasswd = Arc::new(Htpasswd::load(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let auth = BasicAuth::new(htpasswd, "test", std::convert::identity::<Bytes>);
+        // ci:s3cr3t, then ci:wrong
+        let req = |credentials: &str| {
+            Request::get("/")
+                .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+                .body(())
+                .unwrap()
+        };
+
+        let authorized = auth.authorize(req("Y2k6czNjcjN0")).await.unwrap();
+        assert_eq!(
+            authorized.extensions().get::<Principal>().unwrap().name,
+            "ci"
+        );
+        let resp = auth.authorize(req("Y2k6d3Jvbmc=")).await.unwrap_err();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // of the client certificate, whatever the credentials
+        let cert = Principal {
+            name: "deployer".to_owned(),
+            roles: vec![],
+        };
+        for credentials in ["Y2k6czNjcjN0", "Y2k6d3Jvbmc="] {
+            let mut req = req(credentials);
+            req.extensions_mut().insert(cert.clone());
+            let authorized = auth.authorize(req).await.unwrap();
+            assert_eq!(authorized.extensions().get::<Principal>(), Some(&cert));
+        }
+    }
+}
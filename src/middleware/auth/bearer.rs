@@ -0,0 +1,280 @@
+use super::AsyncRequireAuthorization;
+use crate::http::problem::Problem;
+use bytes::Bytes;
+use hyper::http::{Extensions, HeaderValue};
+use hyper::{header, Request, Response, StatusCode};
+use std::{fmt, future::Ready, path::Path, sync::Arc};
+
+/// Who a request is authenticated as, inserted into the request extensions by [`BearerAuth`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Principal {
+    pub name: String,
+    /// roles or scopes granted to the principal, checked by [`super::Policy`]
+    pub roles: Vec<String>,
+}
+
+/// Why a token has been rejected, sent back as the `error_description` of the challenge.
+#[derive(Debug)]
+pub struct InvalidToken(pub String);
+
+impl fmt::Display for InvalidToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Validates bearer tokens, e.g. against a list of tokens or the signature of a JWT.
+pub trait TokenVerifier: Send + Sync {
+    /// Values known from the token besides the principal, such as the claims of a JWT, are
+    /// inserted into `extensions`, the request extensions.
+    fn verify(&self, token: &str, extensions: &mut Extensions) -> Result<Principal, InvalidToken>;
+}
+
+/// Accept the tokens any of the verifiers accepts, trying them in order.
+impl TokenVerifier for Vec<Arc<dyn TokenVerifier>> {
+    fn verify(&self, token: &str, extensions: &mut Extensions) -> Result<Principal, InvalidToken> {
+        let mut last_err = InvalidToken("no token is accepted".to_owned());
+        for verifier in self {
+            match verifier.verify(token, extensions) {
+                Ok(principal) => return Ok(principal),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+/// Tokens of a file loaded at startup, one `<token> <principal> [<role>,...]` per line.
+///
+/// Blank lines and lines starting with `#` are skipped.
+#[derive(Default)]
+pub struct StaticTokens {
+    tokens: Vec<(String, Principal)>,
+}
+
+impl StaticTokens {
+    pub fn load(path: impl AsRef<Path>) -> Result<StaticTokens, String> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        content.parse()
+    }
+}
+
+impl std::str::FromStr for StaticTokens {
+    type Err = String;
+
+    fn from_str(content: &str) -> Result<StaticTokens, String> {
+        let mut tokens = vec![];
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (token, name, roles) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [token, name] => (token, name, ""),
+                [token, name, roles] => (token, name, roles),
+                _ => {
+                    return Err(format!(
+                        "line {}: expect `<token> <principal> [<role>,...]`",
+                        n + 1
+                    ))
+                }
+            };
+            tokens.push((
+                token.to_owned(),
+                Principal {
+                    name: name.to_owned(),
+                    roles: split_roles(roles),
+                },
+            ));
+        }
+        Ok(StaticTokens { tokens })
+    }
+}
+
+impl TokenVerifier for StaticTokens {
+    fn verify(&self, token: &str, _: &mut Extensions) -> Result<Principal, InvalidToken> {
+        // compare with every token, so that the time taken tells nothing about them
+        let mut found = None;
+        for (known, principal) in &self.tokens {
+            if constant_time_eq(known.as_bytes(), token.as_bytes()) {
+                found = Some(principal);
+            }
+        }
+        found
+            .cloned()
+            .ok_or_else(|| InvalidToken("unknown token".to_owned()))
+    }
+}
+
+/// Roles of a comma separated list, e.g. `admin,ops`.
+pub(crate) fn split_roles(roles: &str) -> Vec<String> {
+    roles
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Compare in a time depending only on the lengths.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Authorize requests carrying `Authorization: Bearer <token>` accepted by the verifier, see
+/// [`AsyncRequireAuthorization`]; the [`Principal`] is inserted into the request extensions.
+/// Requests with a principal already, authenticated by an outer layer, are let through.
+///
+/// Other requests are answered `401` with a `WWW-Authenticate` challenge (RFC 6750), `body`
+/// makes the response body of the problem details.
+pub struct BearerAuth<ResBody> {
+    verifier: Arc<dyn TokenVerifier>,
+    realm: Arc<str>,
+    /// of the other schemes accepted, sent along with the bearer one
+    challenges: Vec<HeaderValue>,
+    body: fn(Bytes) -> ResBody,
+    required: bool,
+}
+
+impl<ResBody> Clone for BearerAuth<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            verifier: self.verifier.clone(),
+            realm: self.realm.clone(),
+            challenges: self.challenges.clone(),
+            body: self.body,
+            required: self.required,
+        }
+    }
+}
+
+impl<ResBody> BearerAuth<ResBody> {
+    pub fn new(
+        verifier: Arc<dyn TokenVerifier>,
+        realm: &str,
+        body: fn(Bytes) -> ResBody,
+    ) -> BearerAuth<ResBody> {
+        BearerAuth {
+            verifier,
+            realm: realm.into(),
+            challenges: vec![],
+            body,
+            required: true,
+        }
+    }
+
+    /// Challenge clients to authenticate with another scheme as well, e.g. the one of an outer
+    /// [`super::BasicAuth`], see [`super::BasicAuth::challenge`].
+    pub fn with_challenge(mut self, challenge: HeaderValue) -> Self {
+        self.challenges.push(challenge);
+        self
+    }
+
+    /// Let requests without a token through anonymously, an invalid token is still rejected.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    /// Wrap `inner` to require a valid bearer token.
+    pub fn layer<S>(&self, inner: S) -> AsyncRequireAuthorization<S, Self> {
+        AsyncRequireAuthorization::new(inner, self.clone())
+    }
+
+    fn unauthorized(&self, invalid: Option<InvalidToken>) -> Response<ResBody> {
+        unauthorized(&self.realm, invalid, &self.challenges, self.body)
+    }
+}
+
+/// Answer `401` with the bearer challenge of `realm` followed by the `challenges` of other
+/// schemes, see [`BearerAuth`].
+pub(crate) fn unauthorized<ResBody>(
+    realm: &str,
+    invalid: Option<InvalidToken>,
+    challenges: &[HeaderValue],
+    body: fn(Bytes) -> ResBody,
+) -> Response<ResBody> {
+    let mut challenge = format!("Bearer realm=\"{}\"", realm);
+    let mut problem = Problem::new(StatusCode::UNAUTHORIZED);
+    if let Some(InvalidToken(reason)) = invalid {
+        challenge += &format!(
+            ", error=\"invalid_token\", error_description=\"{}\"",
+            reason.replace(['"', '\\'], "'")
+        );
+        problem = problem.with_detail(reason);
+    }
+    let mut resp = problem.into_response_with(body);
+    if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+        resp.headers_mut()
+            .insert(header::WWW_AUTHENTICATE, challenge);
+    }
+    for challenge in challenges {
+        resp.headers_mut()
+            .append(header::WWW_AUTHENTICATE, challenge.clone());
+    }
+    resp
+}
+
+/// The token of `Authorization: Bearer <token>`, the scheme is case insensitive.
+fn bearer_token<B>(req: &Request<B>) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+impl<B, ResBody> super::AsyncAuthorizeRequest<B> for BearerAuth<ResBody> {
+    type RequestBody = B;
+    type ResponseBody = ResBody;
+    type Future = Ready<Result<Request<B>, Response<ResBody>>>;
+
+    fn authorize(&self, mut req: Request<B>) -> Self::Future {
+        // authenticated by an outer layer already, e.g. with basic credentials
+        if req.extensions().get::<Principal>().is_some() {
+            return std::future::ready(Ok(req));
+        }
+        let token = bearer_token(&req).map(str::to_owned);
+        let verified = token.map(|token| self.verifier.verify(&token, req.extensions_mut()));
+        let result = match verified {
+            Some(Ok(principal)) => {
+                req.extensions_mut().insert(principal);
+                Ok(req)
+            }
+            Some(Err(invalid)) => Err(self.unauthorized(Some(invalid))),
+            None if self.required => Err(self.unauthorized(None)),
+            None => Ok(req),
+        };
+        std::future::ready(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bearer_token, StaticTokens, TokenVerifier};
+    use hyper::{http::Extensions, Request};
+
+    #[test]
+    fn test_static_tokens() {
+        let tokens: StaticTokens = "# ops\nzenx ops admin,ops\n\ns3cr3t ci\n".parse().unwrap();
+        let mut extensions = Extensions::new();
+        assert_eq!(tokens.verify("s3cr3t", &mut extensions).unwrap().name, "ci");
+        assert_eq!(
+            tokens.verify("zenx", &mut extensions).unwrap().roles,
+            ["admin", "ops"]
+        );
+        assert!(tokens.verify("zen", &mut extensions).is_err());
+        assert!("zenx".parse::<StaticTokens>().is_err());
+
+        let req = |value: &str| {
+            Request::get("/")
+                .header("Authorization", value)
+                .body(())
+                .unwrap()
+        };
+        assert_eq!(bearer_token(&req("bearer zenx")), Some("zenx"));
+        assert_eq!(bearer_token(&req("Basic emVueA==")), None);
+        assert_eq!(bearer_token(&req("Bearer ")), None);
+    }
+}
@@ -0,0 +1,85 @@
+use super::{AsyncAuthorizeRequest, AsyncRequireAuthorization, Principal};
+use hyper::{Request, Response};
+use std::{future::Ready, marker::PhantomData};
+
+/// The subject of the certificate a client presented in the TLS handshake, verified against
+/// the client CA and inserted into the extensions of the requests of its connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientCert {
+    /// the whole subject, e.g. `CN=ci, O=deployer`
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub organizations: Vec<String>,
+}
+
+impl ClientCert {
+    /// Read the subject of a DER encoded X.509 certificate.
+    pub fn from_der(der: &[u8]) -> Result<ClientCert, String> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| format!("invalid client certificate: {}", e))?;
+        let subject = cert.subject();
+        let client_cert = ClientCert {
+            subject: subject.to_string(),
+            common_name: subject
+                .iter_common_name()
+                .find_map(|attr| attr.as_str().ok())
+                .map(str::to_owned),
+            organizations: subject
+                .iter_organization()
+                .filter_map(|attr| attr.as_str().ok())
+                .map(str::to_owned)
+                .collect(),
+        };
+        Ok(client_cert)
+    }
+}
+
+/// Authenticate requests of connections with a [`ClientCert`], see
+/// [`AsyncRequireAuthorization`]: the principal is the common name, its roles the
+/// organizations, as Kubernetes does with users and groups.
+///
+/// Other requests are let through to the next authentication, no request is rejected.
+pub struct ClientCertAuth<ResBody> {
+    _body: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> Clone for ClientCertAuth<ResBody> {
+    fn clone(&self) -> Self {
+        ClientCertAuth::new()
+    }
+}
+
+impl<ResBody> ClientCertAuth<ResBody> {
+    pub fn new() -> Self {
+        ClientCertAuth { _body: PhantomData }
+    }
+
+    pub fn layer<S>(&self, inner: S) -> AsyncRequireAuthorization<S, Self> {
+        AsyncRequireAuthorization::new(inner, self.clone())
+    }
+}
+
+impl<ResBody> Default for ClientCertAuth<ResBody> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B, ResBody> AsyncAuthorizeRequest<B> for ClientCertAuth<ResBody> {
+    type RequestBody = B;
+    type ResponseBody = ResBody;
+    type Future = Ready<Result<Request<B>, Response<ResBody>>>;
+
+    fn authorize(&self, mut req: Request<B>) -> Self::Future {
+        let principal = req.extensions().get::<ClientCert>().and_then(|cert| {
+            Some(Principal {
+                name: cert.common_name.clone()?,
+                roles: cert.organizations.clone(),
+            })
+        });
+        if let Some(principal) = principal {
+            req.extensions_mut().insert(principal);
+        }
+        std::future::ready(Ok(req))
+    }
+}
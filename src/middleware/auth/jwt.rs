@@ -0,0 +1,230 @@
+use super::bearer::InvalidToken;
+use super::{Principal, TokenVerifier};
+use hyper::http::Extensions;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet, KeyAlgorithm};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Claims of a verified JWT, inserted into the request extensions by [`JwtVerifier`].
+///
+/// Claims other than the registered ones checked here are kept in `other`, e.g. `aud`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iss: String,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Claims {
+    /// Roles of the `roles` claim, a list, and scopes of the `scope` claim, separated by spaces.
+    pub fn roles(&self) -> Vec<String> {
+        let roles = self.other.get("roles").and_then(|roles| roles.as_array());
+        let roles = roles.into_iter().flatten().filter_map(|role| role.as_str());
+        let scopes = self.other.get("scope").and_then(|scope| scope.as_str());
+        let scopes = scopes.into_iter().flat_map(str::split_whitespace);
+        roles.chain(scopes).map(str::to_owned).collect()
+    }
+}
+
+struct Key {
+    kid: Option<String>,
+    alg: Algorithm,
+    key: DecodingKey,
+}
+
+/// Verify HS256 and RS256 JWTs against the keys of a JWKS, the principal is the `sub` claim.
+///
+/// The signature, `exp`, `nbf`, `iss` and `aud` are all checked, a token is only verified
+/// with a key of the algorithm it names, and of the key id it names if any.
+pub struct JwtVerifier {
+    keys: Vec<Key>,
+    validation: Validation,
+}
+
+impl JwtVerifier {
+    /// Load the keys of a JWKS file, `{"keys": [...]}`.
+    pub fn load(path: impl AsRef<Path>, issuer: &str, audience: &str) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let jwks: JwkSet = serde_json::from_str(&content)
+            .map_err(|e| format!("invalid JWKS {}: {}", path.display(), e))?;
+        Self::new(&jwks, issuer, audience)
+    }
+
+    /// Keys without `alg` are HS256 for `oct` and RS256 for `RSA` keys, others are refused.
+    pub fn new(jwks: &JwkSet, issuer: &str, audience: &str) -> Result<Self, String> {
+        let mut keys = vec![];
+        for (n, jwk) in jwks.keys.iter().enumerate() {
+            let alg = match (&jwk.algorithm, jwk.common.key_algorithm) {
+                (AlgorithmParameters::OctetKey(_), None | Some(KeyAlgorithm::HS256)) => {
+                    Algorithm::HS256
+                }
+                (AlgorithmParameters::RSA(_), None | Some(KeyAlgorithm::RS256)) => Algorithm::RS256,
+                _ => {
+                    return Err(format!(
+                        "key {}: only HS256 and RS256 keys are supported",
+                        n
+                    ))
+                }
+            };
+            let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("key {}: {}", n, e))?;
+            keys.push(Key {
+                kid: jwk.common.key_id.clone(),
+                alg,
+                key,
+            });
+        }
+
+        let mut validation = Validation::default();
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.validate_nbf = true;
+        Ok(JwtVerifier { keys, validation })
+    }
+
+    pub fn verify_claims(&self, token: &str) -> Result<Claims, InvalidToken> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| InvalidToken(format!("malformed token: {}", e)))?;
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+
+        let mut last_err = InvalidToken("no key for the token".to_owned());
+        let candidates = self
+            .keys
+            .iter()
+            .filter(|key| key.alg == header.alg && (header.kid.is_none() || header.kid == key.kid));
+        for key in candidates {
+            match jsonwebtoken::decode::<Claims>(token, &key.key, &validation) {
+                Ok(data) => return Ok(data.claims),
+                Err(e) => last_err = InvalidToken(e.to_string()),
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl TokenVerifier for JwtVerifier {
+    fn verify(&self, token: &str, extensions: &mut Extensions) -> Result<Principal, InvalidToken> {
+        let claims = self.verify_claims(token)?;
+        let principal = Principal {
+            name: claims.sub.clone(),
+            roles: claims.roles(),
+        };
+        extensions.insert(claims);
+        Ok(principal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::JwtVerifier;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+
+    #[test]
+    fn test_verify_hs256() {
+        // "secret" in base64url
+        let jwks = json!({"keys": [{"kty": "oct", "kid": "k1", "k": "c2VjcmV0"}]});
+        let verifier =
+            JwtVerifier::new(&serde_json::from_value(jwks).unwrap(), "gateway", "cars").unwrap();
+        let now = get_current_timestamp();
+        let sign = |claims: serde_json::Value, secret: &[u8], alg: Algorithm| {
+            let mut header = Header::new(alg);
+            header.kid = Some("k1".to_owned());
+            encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+        };
+        let claims = json!({
+            "sub": "ops", "iss": "gateway", "aud": "cars", "exp": now + 60,
+            "roles": ["admin"], "scope": "cars:read cars:write",
+        });
+
+        let token = sign(claims.clone(), b"secret", Algorithm::HS256);
+        let verified = verifier.verify_claims(&token).unwrap();
+        assert_eq!(verified.sub, "ops");
+        assert_eq!(verified.other["aud"], "cars");
+        assert_eq!(verified.roles(), ["admin", "cars:read", "cars:write"]);
+
+        assert!(verifier
+            .verify_claims(&sign(claims.clone(), b"wrong", Algorithm::HS256))
+            .is_err());
+        assert!(verifier
+            .verify_claims(&sign(claims.clone(), b"secret", Algorithm::HS384))
+            .is_err());
+        let mut expired = claims.clone();
+        expired["exp"] = json!(now - 3600);
+        assert!(verifier
+            .verify_claims(&sign(expired, b"secret", Algorithm::HS256))
+            .is_err());
+        let mut early = claims.clone();
+        early["nbf"] = json!(now + 3600);
+        assert!(verifier
+            .verify_claims(&sign(early, b"secret", Algorithm::HS256))
+            .is_err());
+        let mut other_aud = claims;
+        other_aud["aud"] = json!("trucks");
+        assert!(verifier
+            .verify_claims(&sign(other_aud, b"secret", Algorithm::HS256))
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify_rs256() {
+        // a key pair made for these tests, `openssl genrsa -traditional 2048`
+        let jwks = include_str!("testdata/rs256.jwks.json");
+        let verifier =
+            JwtVerifier::new(&serde_json::from_str(jwks).unwrap(), "gateway", "cars").unwrap();
+        let pem: String = include_str!("testdata/rs256.pem")
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let key = EncodingKey::from_rsa_der(&STANDARD.decode(pem).unwrap());
+        let now = get_current_timestamp();
+        let sign = |claims: &serde_json::Value, kid: &str| {
+            let mut header = Header::new(Algorithm::RS256);
+            header.kid = Some(kid.to_owned());
+            encode(&header, claims, &key).unwrap()
+        };
+        let claims = json!({
+            "sub": "ci", "iss": "gateway", "aud": "cars", "exp": now + 60, "nbf": now - 60,
+            "scope": "cars:read",
+        });
+
+        let verified = verifier.verify_claims(&sign(&claims, "rs1")).unwrap();
+        assert_eq!(verified.sub, "ci");
+        assert_eq!(verified.roles(), ["cars:read"]);
+        assert!(verifier.verify_claims(&sign(&claims, "rs2")).is_err());
+
+        let mut expired = claims.clone();
+        expired["exp"] = json!(now - 3600);
+        let err = verifier.verify_claims(&sign(&expired, "rs1")).unwrap_err();
+        assert!(err.0.contains("Expired"), "{}", err);
+        let mut early = claims.clone();
+        early["nbf"] = json!(now + 3600);
+        let err = verifier.verify_claims(&sign(&early, "rs1")).unwrap_err();
+        assert!(err.0.contains("Immature"), "{}", err);
+
+        // alg confusion: an HMAC keyed with the public key the verifier knows must not pass
+        // for the RSA key
+        let modulus = jwks
+            .split("\"n\": \"")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        for secret in [jwks.as_bytes(), modulus.as_bytes()] {
+            let mut header = Header::new(Algorithm::HS256);
+            header.kid = Some("rs1".to_owned());
+            let token = encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap();
+            assert!(verifier.verify_claims(&token).is_err());
+        }
+    }
+}
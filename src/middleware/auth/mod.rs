@@ -1,2 +1,12 @@
 mod async_require_authorization;
-pub use self::async_require_authorization::AsyncRequireAuthorization;
\ No newline at end of file
+mod basic;
+mod bearer;
+mod client_cert;
+mod jwt;
+mod policy;
+pub use self::async_require_authorization::{AsyncAuthorizeRequest, AsyncRequireAuthorization};
+pub use self::basic::{BasicAuth, Htpasswd};
+pub use self::bearer::{BearerAuth, Principal, StaticTokens, TokenVerifier};
+pub use self::client_cert::{ClientCert, ClientCertAuth};
+pub use self::jwt::JwtVerifier;
+pub use self::policy::{Policy, RequireRoles};
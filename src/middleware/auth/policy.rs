@@ -0,0 +1,229 @@
+use super::bearer::{split_roles, unauthorized};
+use super::{AsyncAuthorizeRequest, AsyncRequireAuthorization, Principal};
+use crate::http::{problem::Problem, MatchedPath};
+use bytes::Bytes;
+use hyper::{http::HeaderValue, Method, Request, Response, StatusCode};
+use std::{future::Ready, path::Path, sync::Arc};
+
+/// Roles a route requires of the principal, see [`Policy`].
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+    /// `None` for every method
+    pub method: Option<Method>,
+    /// route pattern as registered, e.g. `/cars/{id}`
+    pub route: String,
+    pub roles: Vec<String>,
+}
+
+/// Why a request is denied by the [`Policy`].
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+    Unauthenticated,
+    /// the principal holds none of these roles
+    Forbidden(Vec<String>),
+}
+
+/// Roles required by the routes, loaded from a file with one rule per line:
+///
+/// ```text
+/// # <method or *> <route pattern> <role>,...
+/// DELETE /cars        admin
+/// POST   /ctl/images  admin,deployer
+/// ```
+///
+/// A request must hold one of the roles of every rule of its method and route, `HEAD`
+/// counting as `GET`; routes without rules are left to the authentication of their group.
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    pub fn load(path: impl AsRef<Path>) -> Result<Policy, String> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        content.parse()
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn check(
+        &self,
+        method: &Method,
+        route: &str,
+        principal: Option<&Principal>,
+    ) -> Result<(), Denied> {
+        let method = if method == Method::HEAD {
+            &Method::GET
+        } else {
+            method
+        };
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.route == route && rule.method.as_ref().is_none_or(|m| m == method));
+        for rule in rules {
+            let principal = principal.ok_or(Denied::Unauthenticated)?;
+            if !rule.roles.iter().any(|role| principal.roles.contains(role)) {
+                return Err(Denied::Forbidden(rule.roles.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    fn from_str(content: &str) -> Result<Policy, String> {
+        let mut rules = vec![];
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [method, route, roles] if route.starts_with('/') => Rule {
+                    method: match method {
+                        "*" => None,
+                        method => Some(
+                            Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                                .map_err(|e| format!("line {}: {}", n + 1, e))?,
+                        ),
+                    },
+                    route: route.to_owned(),
+                    roles: split_roles(roles),
+                },
+                _ => {
+                    return Err(format!(
+                        "line {}: expect `<method or *> <route pattern> <role>,...`",
+                        n + 1
+                    ))
+                }
+            };
+            if rule.roles.is_empty() {
+                return Err(format!("line {}: no role", n + 1));
+            }
+            rules.push(rule);
+        }
+        Ok(Policy { rules })
+    }
+}
+
+/// Enforce the [`Policy`] on requests authenticated by an outer [`super::BearerAuth`], see
+/// [`AsyncRequireAuthorization`].
+///
+/// Anonymous requests are answered `401` with the challenge of `realm`, the others `403`,
+/// which is logged.
+pub struct RequireRoles<ResBody> {
+    policy: Arc<Policy>,
+    realm: Arc<str>,
+    challenges: Vec<HeaderValue>,
+    body: fn(Bytes) -> ResBody,
+}
+
+impl<ResBody> Clone for RequireRoles<ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            policy: self.policy.clone(),
+            realm: self.realm.clone(),
+            challenges: self.challenges.clone(),
+            body: self.body,
+        }
+    }
+}
+
+impl<ResBody> RequireRoles<ResBody> {
+    pub fn new(policy: Arc<Policy>, realm: &str, body: fn(Bytes) -> ResBody) -> Self {
+        RequireRoles {
+            policy,
+            realm: realm.into(),
+            challenges: vec![],
+            body,
+        }
+    }
+
+    /// See [`super::BearerAuth::with_challenge`].
+    pub fn with_challenge(mut self, challenge: HeaderValue) -> Self {
+        self.challenges.push(challenge);
+        self
+    }
+
+    pub fn layer<S>(&self, inner: S) -> AsyncRequireAuthorization<S, Self> {
+        AsyncRequireAuthorization::new(inner, self.clone())
+    }
+
+    fn check<B>(&self, req: &Request<B>) -> Result<(), Response<ResBody>> {
+        // every request within the router has matched a route
+        let Some(MatchedPath(route)) = req.extensions().get::<MatchedPath>() else {
+            return Ok(());
+        };
+        let principal = req.extensions().get::<Principal>();
+        match self.policy.check(req.method(), route, principal) {
+            Ok(()) => Ok(()),
+            Err(Denied::Unauthenticated) => {
+                Err(unauthorized(&self.realm, None, &self.challenges, self.body))
+            }
+            Err(Denied::Forbidden(roles)) => {
+                let roles = roles.join(", ");
+                warn!(
+                    "denied {} {} to {}, who holds none of the roles {}",
+                    req.method(),
+                    route,
+                    principal.map(|p| p.name.as_str()).unwrap_or_default(),
+                    roles
+                );
+                Err(Problem::new(StatusCode::FORBIDDEN)
+                    .with_detail(format!("requires one of the roles {}", roles))
+                    .into_response_with(self.body))
+            }
+        }
+    }
+}
+
+impl<B, ResBody> AsyncAuthorizeRequest<B> for RequireRoles<ResBody> {
+    type RequestBody = B;
+    type ResponseBody = ResBody;
+    type Future = Ready<Result<Request<B>, Response<ResBody>>>;
+
+    fn authorize(&self, req: Request<B>) -> Self::Future {
+        std::future::ready(self.check(&req).map(|()| req))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Denied, Policy};
+    use crate::middleware::auth::Principal;
+    use hyper::Method;
+
+    #[test]
+    fn test_policy() {
+        let policy: Policy = "# wipe\nDELETE /cars admin\n* /ctl/images admin,ops\n"
+            .parse()
+            .unwrap();
+        let ops = Principal {
+            name: "ci".to_owned(),
+            roles: vec!["ops".to_owned()],
+        };
+        assert_eq!(policy.check(&Method::GET, "/cars", None), Ok(()));
+        assert_eq!(
+            policy.check(&Method::DELETE, "/cars", None),
+            Err(Denied::Unauthenticated)
+        );
+        assert_eq!(
+            policy.check(&Method::DELETE, "/cars", Some(&ops)),
+            Err(Denied::Forbidden(vec!["admin".to_owned()]))
+        );
+        assert_eq!(
+            policy.check(&Method::HEAD, "/ctl/images", Some(&ops)),
+            Ok(())
+        );
+
+        assert!("DELETE cars admin".parse::<Policy>().is_err());
+        assert!("DELETE /cars".parse::<Policy>().is_err());
+    }
+}
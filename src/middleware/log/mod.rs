@@ -28,7 +28,7 @@ where
                     this.reqinfo,
                     this.start.elapsed().as_millis()
                 );
-                return Poll::Ready(result);
+                Poll::Ready(result)
             }
             Poll::Pending => Poll::Pending,
         }
@@ -1,7 +1,7 @@
 #[macro_use]
 pub(crate) mod macros;
+pub mod auth;
+pub mod error_handling;
+pub mod log;
 pub mod timeout;
 pub mod util;
-pub mod error_handling;
-pub mod auth;
-pub mod log;
\ No newline at end of file
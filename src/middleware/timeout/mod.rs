@@ -1,17 +1,98 @@
+use crate::http::MatchedPath;
 use pin_project_lite::pin_project;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::task::Poll;
 use std::time::Duration;
 use std::{future::Future, pin::Pin};
+use tokio::time::Instant;
+
+/// Header of a client asking for a response within its milliseconds, it can only shorten the
+/// timeout of the route.
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+
+/// Whose limit a [`Deadline`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Limit {
+    /// the timeout of the route
+    Server,
+    /// the [`REQUEST_TIMEOUT_HEADER`] of the request
+    Client,
+}
+
+/// When the response is due, inserted into the request extensions by [`Timeout`], so that
+/// handlers know the budget they have left.
+///
+/// The work of a handler is dropped at the deadline, child processes should be spawned with
+/// `kill_on_drop` so that they stop with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline {
+    pub at: Instant,
+    /// the server's when both limits fall at the same time
+    pub limit: Limit,
+}
+
+impl Deadline {
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    /// The earliest of `timeout` from now, the timeout asked by the client and the deadline
+    /// of an outer [`Timeout`]; a header which is not a number of milliseconds is ignored.
+    fn of<B>(req: &hyper::Request<B>, timeout: Duration) -> Deadline {
+        let now = Instant::now();
+        let after = |timeout: Duration, limit: Limit| {
+            // too far to be represented is as good as never
+            now.checked_add(timeout).map(|at| Deadline { at, limit })
+        };
+        let requested = req
+            .headers()
+            .get(REQUEST_TIMEOUT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .and_then(|millis| after(Duration::from_millis(millis), Limit::Client));
+        let outer = req.extensions().get::<Deadline>().copied();
+        [after(timeout, Limit::Server), requested, outer]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(Deadline {
+                at: now + Duration::from_secs(86400 * 365),
+                limit: Limit::Server,
+            })
+    }
+}
 
 pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Number of requests timed out per route pattern, counted by [`Timeout::counting`].
+#[derive(Clone, Debug, Default)]
+pub struct Timeouts(Arc<Mutex<BTreeMap<Arc<str>, u64>>>);
+
+impl Timeouts {
+    fn record(&self, MatchedPath(route): &MatchedPath) {
+        *self.0.lock().unwrap().entry(route.clone()).or_default() += 1;
+    }
+
+    /// The counts so far, by route.
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        let counts = self.0.lock().unwrap();
+        counts
+            .iter()
+            .map(|(route, count)| (route.to_string(), *count))
+            .collect()
+    }
+}
+
 pin_project! {
     pub struct ResponseFuture<F> {
         #[pin]
         response_future: F,
         #[pin]
         sleep: tokio::time::Sleep,
+        limit: Limit,
+        counted: Option<(Timeouts, MatchedPath)>,
     }
 }
 
@@ -34,8 +115,13 @@ where
 
         match this.sleep.poll(cx) {
             Poll::Ready(()) => {
+                // the client giving up early is no sign of a slow route
+                if let (Limit::Server, Some((timeouts, route))) = (*this.limit, this.counted.take())
+                {
+                    timeouts.record(&route);
+                }
                 // Construct and return a timeout error.
-                let error = Box::new(TimeoutError(()));
+                let error = Box::new(TimeoutError(*this.limit));
                 return Poll::Ready(Err(error));
             }
             Poll::Pending => {}
@@ -45,45 +131,175 @@ where
     }
 }
 
-#[derive(Debug, Default)]
-pub struct TimeoutError(pub(super) ());
+#[derive(Debug)]
+pub struct TimeoutError(pub(super) Limit);
+
+impl TimeoutError {
+    /// Whose limit was reached.
+    pub fn limit(&self) -> Limit {
+        self.0
+    }
+}
 
 impl fmt::Display for TimeoutError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.pad("request timed out")
+        match self.0 {
+            Limit::Server => f.pad("request timed out"),
+            Limit::Client => f.pad("request deadline exceeded"),
+        }
     }
 }
 
 impl std::error::Error for TimeoutError {}
 
+/// Fail with [`TimeoutError`] once the [`Deadline`] passes, dropping the inner future.
 #[derive(Debug, Clone)]
 pub struct Timeout<S> {
     inner: S,
     timeout: Duration,
+    timeouts: Option<Timeouts>,
 }
 
 impl<S> Timeout<S> {
     pub fn new(inner: S, timeout: Duration) -> Self {
-        Timeout { inner, timeout }
+        Timeout {
+            inner,
+            timeout,
+            timeouts: None,
+        }
+    }
+
+    /// Count the timed out requests in `timeouts` by the [`MatchedPath`] of the request,
+    /// requests outside a router and requests out of their own deadline are not counted.
+    pub fn counting(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
     }
 }
 
-impl<S, Request> hyper::service::Service<Request> for Timeout<S>
+impl<S, B> hyper::service::Service<hyper::Request<B>> for Timeout<S>
 where
-    S: hyper::service::Service<Request>,
+    S: hyper::service::Service<hyper::Request<B>>,
     S::Error: Into<BoxError>,
 {
     type Response = S::Response;
     type Error = BoxError;
     type Future = ResponseFuture<S::Future>;
 
-    fn call(&self, req: Request) -> Self::Future {
+    fn call(&self, mut req: hyper::Request<B>) -> Self::Future {
+        let deadline = Deadline::of(&req, self.timeout);
+        req.extensions_mut().insert(deadline);
+        let route = req.extensions().get::<MatchedPath>().cloned();
+        let counted = self.timeouts.clone().zip(route);
         let response_future = self.inner.call(req);
-        let sleep = tokio::time::sleep(self.timeout);
+        let sleep = tokio::time::sleep_until(deadline.at);
 
         ResponseFuture {
             response_future,
             sleep,
+            limit: deadline.limit,
+            counted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Deadline, Limit, Timeout, TimeoutError, Timeouts, REQUEST_TIMEOUT_HEADER};
+    use crate::http::MatchedPath;
+    use hyper::service::Service;
+    use std::time::Duration;
+    use std::{future::Future, pin::Pin};
+    use tokio::time::Instant;
+
+    /// Answers after a second.
+    struct Slow;
+
+    impl Service<hyper::Request<()>> for Slow {
+        type Response = ();
+        type Error = std::convert::Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<(), Self::Error>> + Send>>;
+
+        fn call(&self, _: hyper::Request<()>) -> Self::Future {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(())
+            })
+        }
+    }
+
+    fn req(route: Option<&str>) -> hyper::Request<()> {
+        let mut req = hyper::Request::new(());
+        if let Some(route) = route {
+            req.extensions_mut().insert(MatchedPath(route.into()));
+        }
+        req
+    }
+
+    #[tokio::test]
+    async fn test_counting() {
+        let timeouts = Timeouts::default();
+        let timeout = Timeout::new(Slow, Duration::from_millis(10)).counting(timeouts.clone());
+        for route in [Some("/cars/{id}"), Some("/cars/{id}"), Some("/cars"), None] {
+            let err = timeout.call(req(route)).await.unwrap_err();
+            assert!(err.is::<TimeoutError>());
+        }
+        let counts = timeouts.counts();
+        assert_eq!(counts.len(), 2, "{:?}", counts);
+        assert_eq!((counts["/cars/{id}"], counts["/cars"]), (2, 1));
+
+        let mut early = req(Some("/cars"));
+        early.headers_mut().insert(REQUEST_TIMEOUT_HEADER, 5.into());
+        let err = timeout.call(early).await.unwrap_err();
+        let err = err.downcast_ref::<TimeoutError>().unwrap();
+        assert_eq!(err.limit(), Limit::Client);
+        assert_eq!(timeouts.counts()["/cars"], 1);
+    }
+
+    fn deadline(header: Option<&str>, outer: Option<Deadline>) -> Deadline {
+        let mut req = req(None);
+        if let Some(header) = header {
+            req.headers_mut()
+                .insert(REQUEST_TIMEOUT_HEADER, header.parse().unwrap());
+        }
+        if let Some(outer) = outer {
+            req.extensions_mut().insert(outer);
+        }
+        Deadline::of(&req, Duration::from_secs(10))
+    }
+
+    #[test]
+    fn test_deadline() {
+        let route = deadline(None, None);
+        assert_eq!(route.limit, Limit::Server);
+        assert!(route.remaining() > Duration::from_secs(9));
+
+        let shorter = deadline(Some("100"), None);
+        assert_eq!(shorter.limit, Limit::Client);
+        assert!(shorter.remaining() <= Duration::from_millis(100));
+        let longer = deadline(Some("60000"), None);
+        assert_eq!(longer.limit, Limit::Server);
+        assert!(longer.remaining() <= Duration::from_secs(10));
+        // a tie is the server's
+        assert_eq!(deadline(Some(" 10000 "), None).limit, Limit::Server);
+
+        let outer = Deadline {
+            at: Instant::now() + Duration::from_millis(50),
+            limit: Limit::Client,
+        };
+        assert_eq!(deadline(Some("100"), Some(outer)), outer);
+        let outer = Deadline {
+            at: Instant::now() + Duration::from_secs(1),
+            limit: Limit::Server,
+        };
+        assert_eq!(deadline(Some("60000"), Some(outer)), outer);
+        assert_eq!(deadline(Some("100"), Some(outer)).limit, Limit::Client);
+
+        let max = u64::MAX.to_string();
+        for bad in ["abc", "-5", "", "1.5", "0x10", &max] {
+            let ignored = deadline(Some(bad), None);
+            assert_eq!(ignored.limit, Limit::Server, "{:?}", bad);
+            assert!(ignored.remaining() > Duration::from_secs(9), "{:?}", bad);
         }
     }
 }
@@ -35,12 +35,47 @@ where
     }
 }
 
+#[derive(Clone)]
+pub struct MapRequest<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F> MapRequest<S, F> {
+    /// Creates a new `MapRequest` service.
+    pub fn new(inner: S, f: F) -> Self {
+        MapRequest { f, inner }
+    }
+}
+
+impl<S, F, R1, R2> hyper::service::Service<R1> for MapRequest<S, F>
+where
+    S: hyper::service::Service<R2>,
+    F: Fn(R1) -> R2,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    #[inline]
+    fn call(&self, request: R1) -> Self::Future {
+        self.inner.call((self.f)(request))
+    }
+}
+
 #[derive(Clone)]
 pub struct MapResult<S, F> {
     inner: S,
     f: F,
 }
 
+impl<S, F> MapResult<S, F> {
+    /// Creates a new `MapResult` service.
+    pub fn new(inner: S, f: F) -> Self {
+        MapResult { f, inner }
+    }
+}
+
 opaque_future! {
     /// Response future from [`MapResult`] services.
     ///
@@ -51,7 +86,6 @@ opaque_future! {
 impl<S, F, Request, Response, Error> hyper::service::Service<Request> for MapResult<S, F>
 where
     S: hyper::service::Service<Request>,
-    Error: From<S::Error>,
     F: FnOnce(Result<S::Response, S::Error>) -> Result<Response, Error> + Clone,
 {
     type Response = Response;
@@ -0,0 +1,346 @@
+//! `Upgrade: h2c` of HTTP/1.1 connections to HTTP/2 (RFC 7540 section 3.2).
+//!
+//! hyper only speaks HTTP/2 with prior knowledge: the request asking for the upgrade is
+//! answered `101`, then replayed to the HTTP/2 server as stream 1, on which the client waits
+//! for its response, right after the SETTINGS of the client preface.
+
+use base64::Engine;
+use bytes::Bytes;
+use futures_util::future::{self, Either as EitherFuture, MapOk, Ready};
+use futures_util::TryFutureExt;
+use http_body_util::{Either, Empty};
+use hyper::body::{Body, Incoming};
+use hyper::service::Service;
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper::{header, Request, Response, StatusCode, Version};
+use hyper_util::rt::TokioIo;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+const HTTP2_SETTINGS: &str = "http2-settings";
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_LEN: usize = 9;
+const HEADERS: u8 = 0x1;
+const SETTINGS: u8 = 0x4;
+const END_STREAM: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+/// SETTINGS_MAX_FRAME_SIZE, until the peer allows larger frames
+const MAX_FRAME_SIZE: usize = 16384;
+
+/// Headers meaningful to a single HTTP/1.1 connection only, not allowed in HTTP/2.
+const CONNECTION_SPECIFIC: [&str; 8] = [
+    "connection",
+    "upgrade",
+    HTTP2_SETTINGS,
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+];
+
+/// A request answered `101`, to be replayed once the connection speaks HTTP/2.
+pub struct Upgrade {
+    /// the payload of `HTTP2-Settings`
+    settings: Vec<u8>,
+    /// the HEADERS frame of stream 1
+    headers: Vec<u8>,
+    on_upgrade: OnUpgrade,
+}
+
+impl Upgrade {
+    /// The connection once switched, read up to the end of the client preface: the
+    /// `HTTP2-Settings` of the upgrade are merged into its SETTINGS, and the request of the
+    /// upgrade comes after it.
+    pub async fn io(self) -> io::Result<Replay<TokioIo<Upgraded>>> {
+        let upgraded = self.on_upgrade.await.map_err(io::Error::other)?;
+        let mut io = TokioIo::new(upgraded);
+        let mut preface = vec![0; PREFACE.len() + FRAME_HEADER_LEN];
+        io.read_exact(&mut preface).await?;
+        let frame = preface.split_off(PREFACE.len());
+        let len = u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+        if preface != PREFACE || frame[3] != SETTINGS || self.settings.len() + len > MAX_FRAME_SIZE
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expect the HTTP/2 client preface",
+            ));
+        }
+        // the values of the preface come last, and win
+        let mut settings = self.settings;
+        let start = settings.len();
+        settings.resize(start + len, 0);
+        io.read_exact(&mut settings[start..]).await?;
+
+        let mut replay = preface;
+        replay.extend_from_slice(&(settings.len() as u32).to_be_bytes()[1..]);
+        replay.extend_from_slice(&frame[3..]);
+        replay.extend(settings);
+        replay.extend(self.headers);
+        Ok(Replay {
+            replay: Bytes::from(replay),
+            io,
+        })
+    }
+}
+
+/// A connection whose first bytes were read ahead, and are read again.
+pub struct Replay<I> {
+    replay: Bytes,
+    io: I,
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Replay<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.replay.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+        let n = self.replay.len().min(buf.remaining());
+        buf.put_slice(&self.replay.split_to(n));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for Replay<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// Answer `101` to the HTTP/1.1 requests asking to upgrade to h2c, keeping the [`Upgrade`]
+/// for the connection to carry on with; the other requests go to the inner service.
+///
+/// A request with a body is served in HTTP/1.1, as the body would have to be read before
+/// switching.
+#[derive(Clone)]
+pub struct UpgradeH2c<S> {
+    inner: S,
+    enabled: bool,
+    upgrade: Arc<Mutex<Option<Upgrade>>>,
+}
+
+impl<S> UpgradeH2c<S> {
+    /// Upgrade only if `enabled`, h2c is not for TLS connections, which negotiate h2 by ALPN.
+    pub fn new(inner: S, enabled: bool) -> Self {
+        UpgradeH2c {
+            inner,
+            enabled,
+            upgrade: Default::default(),
+        }
+    }
+
+    /// The upgrade answered, the HTTP/1.1 connection ends with it.
+    pub fn take(&self) -> Option<Upgrade> {
+        self.upgrade.lock().unwrap().take()
+    }
+}
+
+type UpgradeResponse<B> = Response<Either<B, Empty<<B as Body>::Data>>>;
+
+impl<S, B> Service<Request<Incoming>> for UpgradeH2c<S>
+where
+    S: Service<Request<Incoming>, Response = Response<B>>,
+    B: Body,
+{
+    type Response = UpgradeResponse<B>;
+    type Error = S::Error;
+    type Future = EitherFuture<
+        MapOk<S::Future, fn(Response<B>) -> UpgradeResponse<B>>,
+        Ready<Result<UpgradeResponse<B>, S::Error>>,
+    >;
+
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        let upgrade = self.enabled.then(|| upgrade_request(&req)).flatten();
+        let on_upgrade = upgrade
+            .is_some()
+            .then(|| req.extensions_mut().remove::<OnUpgrade>())
+            .flatten();
+        let (Some((settings, headers)), Some(on_upgrade)) = (upgrade, on_upgrade) else {
+            let left: fn(Response<B>) -> UpgradeResponse<B> = |resp| resp.map(Either::Left);
+            return EitherFuture::Left(self.inner.call(req).map_ok(left));
+        };
+        *self.upgrade.lock().unwrap() = Some(Upgrade {
+            settings,
+            headers,
+            on_upgrade,
+        });
+        let mut resp = Response::new(Either::Right(Empty::new()));
+        *resp.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = resp.headers_mut();
+        headers.insert(
+            header::CONNECTION,
+            header::HeaderValue::from_static("Upgrade"),
+        );
+        headers.insert(header::UPGRADE, header::HeaderValue::from_static("h2c"));
+        EitherFuture::Right(future::ready(Ok(resp)))
+    }
+}
+
+/// The `HTTP2-Settings` payload and the HEADERS frame of stream 1 of a request to upgrade to
+/// h2c, `None` for other requests.
+fn upgrade_request<B>(req: &Request<B>) -> Option<(Vec<u8>, Vec<u8>)> {
+    let headers = req.headers();
+    let has_token = |name: header::HeaderName, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    if req.version() != Version::HTTP_11
+        || !has_token(header::UPGRADE, "h2c")
+        || !has_token(header::CONNECTION, "upgrade")
+        || !has_token(header::CONNECTION, HTTP2_SETTINGS)
+        || headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .is_some_and(|len| len != "0")
+    {
+        return None;
+    }
+    // exactly one, of whole settings
+    let mut settings = headers.get_all(HTTP2_SETTINGS).iter();
+    let (Some(settings), None) = (settings.next(), settings.next()) else {
+        return None;
+    };
+    let settings = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(settings.as_bytes().trim_ascii())
+        .ok()
+        .filter(|settings| settings.len() % 6 == 0 && settings.len() <= MAX_FRAME_SIZE)?;
+
+    let mut block = vec![];
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    literal(&mut block, b":method", req.method().as_str().as_bytes());
+    literal(&mut block, b":scheme", b"http");
+    literal(&mut block, b":path", path.as_bytes());
+    if let Some(host) = headers.get(header::HOST) {
+        literal(&mut block, b":authority", host.as_bytes());
+    }
+    for (name, value) in headers {
+        if !CONNECTION_SPECIFIC.contains(&name.as_str()) {
+            literal(&mut block, name.as_str().as_bytes(), value.as_bytes());
+        }
+    }
+    if block.len() > MAX_FRAME_SIZE {
+        return None;
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + block.len());
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    frame.extend_from_slice(&[HEADERS, END_STREAM | END_HEADERS]);
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.extend(block);
+    Some((settings, frame))
+}
+
+/// A header field as an HPACK literal without indexing (RFC 7541 section 6.2.2), which
+/// leaves the dynamic table of the connection as the client knows it.
+fn literal(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    for string in [name, value] {
+        // the length, an integer of a 7 bits prefix after the Huffman bit, unset
+        let mut len = string.len();
+        if len < 0x7f {
+            block.push(len as u8);
+        } else {
+            block.push(0x7f);
+            len -= 0x7f;
+            while len >= 0x80 {
+                block.push(len as u8 | 0x80);
+                len >>= 7;
+            }
+            block.push(len as u8);
+        }
+        block.extend_from_slice(string);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{literal, upgrade_request};
+    use hyper::Request;
+
+    fn upgrade(req: hyper::http::request::Builder) -> Option<(Vec<u8>, Vec<u8>)> {
+        upgrade_request(&req.body(()).unwrap())
+    }
+
+    #[test]
+    fn test_upgrade_request() {
+        let req = || {
+            Request::get("/cars?brand=audi")
+                .header("host", "localhost")
+                .header("connection", "Upgrade, HTTP2-Settings")
+                .header("upgrade", "h2c")
+                .header("accept", "*/*")
+        };
+        let (settings, frame) = upgrade(req().header("http2-settings", "AAMAAABk")).unwrap();
+        // SETTINGS_MAX_CONCURRENT_STREAMS 100
+        assert_eq!(settings, [0, 3, 0, 0, 0, 100]);
+        assert_eq!(frame[3..9], [1, 5, 0, 0, 0, 1]);
+        let mut block = vec![];
+        literal(&mut block, b":method", b"GET");
+        literal(&mut block, b":scheme", b"http");
+        literal(&mut block, b":path", b"/cars?brand=audi");
+        literal(&mut block, b":authority", b"localhost");
+        literal(&mut block, b"accept", b"*/*");
+        assert_eq!(frame[9..], block);
+        assert_eq!(frame[..3], [0, 0, block.len() as u8]);
+
+        assert!(upgrade(req()).is_none());
+        assert!(upgrade(req().header("http2-settings", "AAMAAA")).is_none());
+        assert!(upgrade(req().header("http2-settings", "not base64")).is_none());
+        let two = req()
+            .header("http2-settings", "AAMAAABk")
+            .header("http2-settings", "AAMAAABk");
+        assert!(upgrade(two).is_none());
+        let body = req()
+            .header("http2-settings", "")
+            .header("content-length", "2");
+        assert!(upgrade(body).is_none());
+        let websocket = Request::get("/")
+            .header("connection", "Upgrade, HTTP2-Settings")
+            .header("upgrade", "websocket")
+            .header("http2-settings", "");
+        assert!(upgrade(websocket).is_none());
+    }
+
+    #[test]
+    fn test_literal() {
+        let mut block = vec![];
+        literal(&mut block, b"a", &[b'x'; 1337]);
+        // 1337 = 127 + 1210, 1210 = 0b1001_0111010
+        assert_eq!(block[..6], [0, 1, b'a', 0x7f, 0xba, 0x09]);
+        assert_eq!(block.len(), 6 + 1337);
+    }
+}
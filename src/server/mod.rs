@@ -0,0 +1,637 @@
+mod h2c;
+
+use crate::config::Http2Config;
+use crate::middleware;
+use crate::tls;
+use hyper::body::{Body, Incoming};
+use hyper::service::Service;
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use std::future::Future;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+
+/// How long a client may take to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a client may take to send the HTTP/2 preface once an h2c upgrade is answered.
+const H2C_PREFACE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a keep-alive ping may go unanswered before the HTTP/2 connection is closed.
+const H2_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A connection, TLS or not.
+trait Io: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> Io for T {}
+
+/// HTTP/1.1, or HTTP/2 for clients speaking it with prior knowledge, told apart by the
+/// connection preface, with the concurrent streams and keep-alive pings of `http2`.
+pub fn conn_builder(http2: &Http2Config) -> auto::Builder<TokioExecutor> {
+    // pings detect dead HTTP/2 connections, whose requests would otherwise hang until drained
+    let keep_alive = (http2.keep_alive > 0).then(|| Duration::from_secs(http2.keep_alive));
+    let mut conn_builder = auto::Builder::new(TokioExecutor::new());
+    conn_builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(http2.max_streams)
+        .keep_alive_interval(keep_alive)
+        .keep_alive_timeout(H2_KEEP_ALIVE_TIMEOUT);
+    conn_builder
+}
+
+/// Resolve once connections are told to finish their in-flight requests and close, at once if
+/// they were already.
+async fn draining(rx: &mut watch::Receiver<bool>) {
+    let _ = rx.wait_for(|&draining| draining).await;
+}
+
+/// How the connections of a listener are served, see [`Server::run`].
+pub struct Server {
+    /// see [`conn_builder`], HTTP/1.1 connections of plain TCP may also switch to HTTP/2 by
+    /// `Upgrade: h2c`
+    pub conn_builder: auto::Builder<TokioExecutor>,
+    pub tls: Option<TlsAcceptor>,
+    /// how long in-flight requests may take to finish at shutdown
+    pub drain: Duration,
+}
+
+impl Server {
+    /// Serve the connections of `listener` until `shutdown` resolves, to the signal received
+    /// or the error listening for it, which is logged, then stop accepting and let the open
+    /// connections finish their in-flight requests, for [`Server::drain`] at most;
+    /// connections still open then are aborted, which is an error.
+    pub async fn run<S, B, E>(
+        self,
+        listener: TcpListener,
+        svc: S,
+        shutdown: impl Future<Output = Result<&'static str, String>>,
+    ) -> Result<(), tower::BoxError>
+    where
+        S: Service<Request<Incoming>, Response = Response<B>, Error = E> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<tower::BoxError>,
+        E: Into<tower::BoxError> + Send + 'static,
+    {
+        // connections are told to finish their in-flight requests and close once this turns true
+        let (tx, rx) = watch::channel(false);
+        let mut conns = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                res = listener.accept() => {
+                    let (stream, peer) = res.expect("Failed to accept");
+                    let tls = self.tls.clone();
+                    let svc = svc.clone();
+                    let conn_builder = self.conn_builder.clone();
+                    let mut rx = rx.clone();
+                    conns.spawn(async move {
+                        let plain = tls.is_none();
+                        let (stream, client_cert): (Box<dyn Io>, _) = match tls {
+                            None => (Box::new(stream), None),
+                            Some(tls) => {
+                                let handshake = tls.accept(stream);
+                                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                                    Ok(Ok(stream)) => {
+                                        let client_cert = tls::client_cert(stream.get_ref().1);
+                                        (Box::new(stream), client_cert)
+                                    }
+                                    Ok(Err(err)) => return warn!("TLS handshake with {} failed: {}", peer, err),
+                                    Err(_) => return warn!("TLS handshake with {} timed out", peer),
+                                }
+                            }
+                        };
+                        // for the auth middleware, see `Auth::guard`
+                        let svc = middleware::util::MapRequest::new(svc, move |mut req: Request<Incoming>| {
+                            if let Some(client_cert) = &client_cert {
+                                req.extensions_mut().insert(client_cert.clone());
+                            }
+                            req
+                        });
+                        let svc = h2c::UpgradeH2c::new(svc, plain);
+
+                        // Use an adapter to access something implementing `tokio::io` traits as if they implement
+                        // `hyper::rt` IO traits.
+                        // fix: the trait `hyper::rt::Read` is not implemented for `tokio::net::TcpStream`
+                        let io = TokioIo::new(stream);
+                        let conn = conn_builder.serve_connection_with_upgrades(io, svc.clone());
+                        tokio::pin!(conn);
+                        let res = tokio::select! {
+                            res = conn.as_mut() => res,
+                            _ = draining(&mut rx) => {
+                                // stop reading new requests, the connection must still be polled to
+                                // answer the one in flight
+                                conn.as_mut().graceful_shutdown();
+                                conn.await
+                            }
+                        };
+                        if let Err(err) = res {
+                            return println!("Error serving connection: {:?}", err);
+                        }
+
+                        // the HTTP/1.1 connection ended with a switch to HTTP/2
+                        let Some(upgrade) = svc.take() else { return };
+                        let io = match tokio::time::timeout(H2C_PREFACE_TIMEOUT, upgrade.io()).await {
+                            Ok(Ok(io)) => io,
+                            Ok(Err(err)) => return warn!("h2c upgrade of {} failed: {}", peer, err),
+                            Err(_) => return warn!("h2c upgrade of {} timed out", peer),
+                        };
+                        let conn = conn_builder.serve_connection(TokioIo::new(io), svc);
+                        tokio::pin!(conn);
+                        let res = tokio::select! {
+                            res = conn.as_mut() => res,
+                            _ = draining(&mut rx) => {
+                                conn.as_mut().graceful_shutdown();
+                                conn.await
+                            }
+                        };
+                        if let Err(err) = res {
+                            println!("Error serving connection: {:?}", err);
+                        }
+                    });
+                }
+                // reap finished connections, so that the set holds only the open ones
+                Some(_) = conns.join_next(), if !conns.is_empty() => {}
+                signal = &mut shutdown => {
+                    match signal {
+                        Ok(signal) => info!("received {}, draining {} connections", signal, conns.len()),
+                        // the server cannot be told to stop anymore, so stop now, gracefully
+                        Err(e) => error!("{}, draining {} connections", e, conns.len()),
+                    }
+                    break;
+                }
+            }
+        }
+
+        drop(listener);
+        let _ = tx.send(true);
+        let drain = async { while conns.join_next().await.is_some() {} };
+        match tokio::time::timeout(self.drain, drain).await {
+            Ok(()) => {
+                info!("all connections drained");
+                Ok(())
+            }
+            Err(_) => {
+                let open = conns.len();
+                conns.abort_all();
+                Err(format!(
+                    "{} connections still open after DRAIN_TIMEOUT of {:?}, aborted",
+                    open, self.drain
+                )
+                .into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{conn_builder, Server};
+    use crate::config::Http2Config;
+    use crate::middleware::auth::{AsyncAuthorizeRequest, ClientCertAuth, Principal};
+    use crate::tls::{self, TlsConfig};
+    use crate::{full, BoxBody};
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::{Request, Response, StatusCode, Version};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::pki_types::{PrivatePkcs8KeyDer, ServerName};
+    use rustls::{ClientConfig, RootCertStore};
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::TlsConnector;
+
+    /// The client preface, with empty SETTINGS.
+    const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0";
+    const DATA: u8 = 0x0;
+    const HEADERS: u8 = 0x1;
+    const SETTINGS: u8 = 0x4;
+    const PING: u8 = 0x6;
+    const END_STREAM: u8 = 0x1;
+    const ACK: u8 = 0x1;
+
+    /// `GET /sleep/<milliseconds>` answers once they have passed, other requests are echoed
+    /// with the principal of their client certificate, `-` without one.
+    async fn svc(req: Request<Incoming>) -> Result<Response<BoxBody>, hyper::Error> {
+        if let Some(millis) = req.uri().path().strip_prefix("/sleep/") {
+            tokio::time::sleep(Duration::from_millis(millis.parse().unwrap())).await;
+            return Ok(Response::new(full("slept")));
+        }
+        // as the outermost layer of `Auth::guard`
+        let Ok(req) = ClientCertAuth::<BoxBody>::new().authorize(req).await else {
+            unreachable!("no request is rejected")
+        };
+        let principal = req
+            .extensions()
+            .get::<Principal>()
+            .map_or("-".to_owned(), |p| {
+                format!("{} {}", p.name, p.roles.join(","))
+            });
+        let echo = format!(
+            "{:?} {} {} {}",
+            req.version(),
+            req.method(),
+            req.uri(),
+            principal
+        );
+        Ok(Response::new(full(echo)))
+    }
+
+    fn plain(drain: Duration) -> Server {
+        Server {
+            conn_builder: conn_builder(&Http2Config::default()),
+            tls: None,
+            drain,
+        }
+    }
+
+    type Shutdown = oneshot::Sender<Result<&'static str, String>>;
+
+    /// `server` of [`svc`] on a free port, shut down when the sender is dropped, or with the
+    /// signal it sends.
+    async fn start(
+        server: Server,
+    ) -> (
+        SocketAddr,
+        Shutdown,
+        JoinHandle<Result<(), tower::BoxError>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel();
+        let shutdown = async move { rx.await.unwrap_or(Ok("shutdown")) };
+        let svc = hyper::service::service_fn(svc);
+        let run = tokio::spawn(server.run(listener, svc, shutdown));
+        (addr, tx, run)
+    }
+
+    /// Send `GET <path>` on a new connection, the response comes in the background.
+    async fn get(
+        addr: SocketAddr,
+        path: &str,
+    ) -> JoinHandle<Result<Response<Incoming>, hyper::Error>> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let req = Request::get(path).body(Empty::<Bytes>::new()).unwrap();
+        tokio::spawn(async move { sender.send_request(req).await })
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        // also when the signal could not be listened for
+        for signal in [Ok("SIGTERM"), Err("no signal handler".to_owned())] {
+            let (addr, shutdown, run) = start(plain(Duration::from_secs(2))).await;
+            let in_flight = get(addr, "/sleep/300").await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            shutdown.send(signal).unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            // no more connections, the request in flight is still answered
+            assert!(TcpStream::connect(addr).await.is_err());
+            let resp = in_flight.await.unwrap().unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "slept");
+            run.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let (addr, shutdown, run) = start(plain(Duration::from_millis(100))).await;
+        let in_flight = get(addr, "/sleep/5000").await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(shutdown);
+
+        // closed once the drain timeout passes, well before the handler is done
+        let err = tokio::time::timeout(Duration::from_secs(1), run)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("1 connections still open"),
+            "{}",
+            err
+        );
+        let closed = tokio::time::timeout(Duration::from_secs(1), in_flight)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(closed.is_err());
+    }
+
+    /// The next frame: type, flags, stream and payload.
+    async fn frame(io: &mut TcpStream) -> (u8, u8, u32, Vec<u8>) {
+        let mut header = [0; 9];
+        io.read_exact(&mut header).await.unwrap();
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+        let mut payload = vec![0; len as usize];
+        io.read_exact(&mut payload).await.unwrap();
+        let stream = u32::from_be_bytes(header[5..].try_into().unwrap());
+        (header[3], header[4], stream, payload)
+    }
+
+    #[tokio::test]
+    async fn test_http2() {
+        let http2 = Http2Config {
+            max_streams: 7,
+            keep_alive: 1,
+        };
+        let server = Server {
+            conn_builder: conn_builder(&http2),
+            tls: None,
+            drain: Duration::from_secs(1),
+        };
+        let (addr, _shutdown, _run) = start(server).await;
+
+        // with prior knowledge
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await
+                .unwrap();
+        tokio::spawn(conn);
+        let req = Request::get("http://localhost/echo")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.version(), Version::HTTP_2);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "HTTP/2.0 GET http://localhost/echo -");
+
+        // the settings as sent, then a ping once the connection has been idle for a second
+        let mut io = TcpStream::connect(addr).await.unwrap();
+        io.write_all(PREFACE).await.unwrap();
+        let (kind, _, _, payload) = frame(&mut io).await;
+        assert_eq!(kind, SETTINGS);
+        let settings: Vec<(u16, u32)> = payload
+            .chunks(6)
+            .map(|s| {
+                (
+                    u16::from_be_bytes([s[0], s[1]]),
+                    u32::from_be_bytes([s[2], s[3], s[4], s[5]]),
+                )
+            })
+            .collect();
+        // SETTINGS_MAX_CONCURRENT_STREAMS
+        assert!(settings.contains(&(0x3, 7)), "{:?}", settings);
+        let ping = async {
+            loop {
+                let (kind, flags, _, _) = frame(&mut io).await;
+                if kind == PING && flags & ACK == 0 {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(3), ping)
+            .await
+            .expect("no keep-alive ping");
+    }
+
+    #[tokio::test]
+    async fn test_h2c_upgrade() {
+        let (addr, _shutdown, _run) = start(plain(Duration::from_secs(1))).await;
+        let upgrade = |settings: &str| {
+            format!(
+                "GET /echo?upgraded=1 HTTP/1.1\r\nHost: localhost\r\n\
+                 Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n\
+                 HTTP2-Settings: {}\r\n\r\n",
+                settings
+            )
+        };
+
+        let mut io = TcpStream::connect(addr).await.unwrap();
+        io.write_all(upgrade("AAMAAABk").as_bytes()).await.unwrap();
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(io.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap().to_lowercase();
+        assert!(
+            head.starts_with("http/1.1 101 switching protocols\r\n"),
+            "{}",
+            head
+        );
+        assert!(head.contains("\r\nupgrade: h2c\r\n"), "{}", head);
+
+        // the response to the upgrade comes on stream 1
+        io.write_all(PREFACE).await.unwrap();
+        let (mut status, mut body) = (None, vec![]);
+        let response = async {
+            loop {
+                let (kind, flags, stream, payload) = frame(&mut io).await;
+                match (kind, stream) {
+                    (HEADERS, 1) => status = Some(payload[0]),
+                    (DATA, 1) => {
+                        body.extend(payload);
+                        if flags & END_STREAM != 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(3), response)
+            .await
+            .unwrap();
+        // `:status: 200`, indexed in the static table
+        assert_eq!(status, Some(0x88));
+        assert_eq!(body, b"HTTP/2.0 GET http://localhost/echo?upgraded=1 -");
+
+        // malformed settings, not upgraded
+        let mut io = TcpStream::connect(addr).await.unwrap();
+        let req = upgrade("not base64").replace("Host:", "Connection: close\r\nHost:");
+        io.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        io.read_to_string(&mut resp).await.unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(
+            resp.ends_with("HTTP/1.1 GET /echo?upgraded=1 -"),
+            "{}",
+            resp
+        );
+    }
+
+    /// A certificate and its key.
+    struct Issued {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    fn ca(cn: &str) -> Issued {
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Issued { cert, key }
+    }
+
+    /// A certificate of `localhost`, for the common name `cn` of the organization `deployer`.
+    fn issue(ca: &Issued, cn: &str, usage: ExtendedKeyUsagePurpose) -> Issued {
+        let mut params = CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, "deployer");
+        params.extended_key_usages = vec![usage];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca.cert, &ca.key).unwrap();
+        Issued { cert, key }
+    }
+
+    /// A TLS server of [`svc`] with a certificate of `ca`, which also issues the client
+    /// certificates.
+    async fn start_tls(
+        dir: &PathBuf,
+        ca: &Issued,
+        require_client_cert: bool,
+    ) -> (SocketAddr, Shutdown) {
+        std::fs::create_dir_all(dir).unwrap();
+        let server = issue(ca, "server", ExtendedKeyUsagePurpose::ServerAuth);
+        let config = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            client_ca: Some(dir.join("ca.pem")),
+            require_client_cert,
+        };
+        std::fs::write(&config.cert, server.cert.pem()).unwrap();
+        std::fs::write(&config.key, server.key.serialize_pem()).unwrap();
+        std::fs::write(config.client_ca.as_ref().unwrap(), ca.cert.pem()).unwrap();
+        let mut server = plain(Duration::from_secs(1));
+        server.tls = Some(tls::acceptor(&config).unwrap());
+        let (addr, shutdown, _) = start(server).await;
+        (addr, shutdown)
+    }
+
+    /// Connect trusting `ca`, offering the protocols `alpn` and presenting `cert` if any.
+    async fn connect(
+        addr: SocketAddr,
+        ca: &Issued,
+        alpn: &[&[u8]],
+        cert: Option<&Issued>,
+    ) -> std::io::Result<TlsStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = match cert {
+            Some(cert) => {
+                let key = PrivatePkcs8KeyDer::from(cert.key.serialize_der());
+                builder
+                    .with_client_auth_cert(vec![cert.cert.der().clone()], key.into())
+                    .unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        let stream = TcpStream::connect(addr).await?;
+        let localhost = ServerName::try_from("localhost").unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(localhost, stream)
+            .await
+    }
+
+    /// `GET /whoami` over `stream`, in HTTP/2 if the server chose `h2`.
+    async fn whoami(stream: TlsStream<TcpStream>) -> Result<String, hyper::Error> {
+        let h2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+        let io = TokioIo::new(stream);
+        let resp = if h2 {
+            let (mut sender, conn) =
+                hyper::client::conn::http2::handshake(TokioExecutor::new(), io).await?;
+            tokio::spawn(conn);
+            let req = Request::get("https://localhost/whoami");
+            sender
+                .send_request(req.body(Empty::<Bytes>::new()).unwrap())
+                .await?
+        } else {
+            let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
+            tokio::spawn(conn);
+            let req = Request::get("/whoami").header("host", "localhost");
+            sender
+                .send_request(req.body(Empty::<Bytes>::new()).unwrap())
+                .await?
+        };
+        let body = resp.into_body().collect().await?.to_bytes();
+        Ok(String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Whether the server refuses the client, which with TLS 1.3 only shows once the
+    /// handshake is over on the client's side.
+    async fn refused(stream: std::io::Result<TlsStream<TcpStream>>) -> bool {
+        match stream {
+            Ok(stream) => whoami(stream).await.is_err(),
+            Err(_) => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        let dir = std::env::temp_dir().join(format!("server-tls-{}", std::process::id()));
+        let (ca, other) = (ca("clients"), ca("other"));
+        let (addr, _shutdown) = start_tls(&dir, &ca, false).await;
+        let client = issue(&ca, "ci", ExtendedKeyUsagePurpose::ClientAuth);
+        let both: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+        // h2 when offered, anonymous without a certificate
+        let stream = connect(addr, &ca, both, None).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        let echo = whoami(stream).await.unwrap();
+        assert_eq!(echo, "HTTP/2.0 GET https://localhost/whoami -");
+
+        // the certificate maps to the principal and its roles
+        let stream = connect(addr, &ca, &[b"http/1.1"], Some(&client)).await;
+        let stream = stream.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        let echo = whoami(stream).await.unwrap();
+        assert_eq!(echo, "HTTP/1.1 GET /whoami ci deployer");
+        let stream = connect(addr, &ca, both, Some(&client)).await.unwrap();
+        let echo = whoami(stream).await.unwrap();
+        assert_eq!(echo, "HTTP/2.0 GET https://localhost/whoami ci deployer");
+
+        // a certificate presented must be of the CA
+        let stranger = issue(&other, "ci", ExtendedKeyUsagePurpose::ClientAuth);
+        assert!(refused(connect(addr, &ca, both, Some(&stranger)).await).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tls_require_client_cert() {
+        let dir = std::env::temp_dir().join(format!("server-mtls-{}", std::process::id()));
+        let (ca, other) = (ca("clients"), ca("other"));
+        let (addr, _shutdown) = start_tls(&dir, &ca, true).await;
+        let both: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+        assert!(refused(connect(addr, &ca, both, None).await).await);
+        let stranger = issue(&other, "ci", ExtendedKeyUsagePurpose::ClientAuth);
+        assert!(refused(connect(addr, &ca, both, Some(&stranger)).await).await);
+        let client = issue(&ca, "ci", ExtendedKeyUsagePurpose::ClientAuth);
+        let stream = connect(addr, &ca, both, Some(&client)).await.unwrap();
+        let echo = whoami(stream).await.unwrap();
+        assert_eq!(echo, "HTTP/2.0 GET https://localhost/whoami ci deployer");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
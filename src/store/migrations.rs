@@ -0,0 +1,160 @@
+use super::StoreError;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Schema changes of the cars database, applied in order and never edited once released.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create cars",
+        sql: "create table if not exists cars (
+                  id integer primary key autoincrement,
+                  brand text not null,
+                  model text not null,
+                  year integer
+              );",
+    },
+    Migration {
+        version: 2,
+        name: "index cars for listing",
+        sql: "create index if not exists cars_brand on cars (brand, id);
+              create index if not exists cars_year on cars (year, id);",
+    },
+    Migration {
+        version: 3,
+        name: "add car revision",
+        sql: "alter table cars add column revision integer not null default 1;",
+    },
+    Migration {
+        version: 4,
+        name: "require car year",
+        // sqlite cannot add not null to a column, the table is rebuilt; a legacy row without
+        // a year fails the migration, to be given one by hand before migrating again
+        sql: "create table cars_new (
+                  id integer primary key autoincrement,
+                  brand text not null,
+                  model text not null,
+                  year integer not null,
+                  revision integer not null default 1
+              );
+              insert into cars_new (id, brand, model, year, revision)
+                  select id, brand, model, year, revision from cars;
+              -- ids of deleted cars are still never reused, even with no car left, when
+              -- cars_new has no sequence of its own yet
+              insert into sqlite_sequence (name, seq)
+                  select 'cars_new', 0
+                  where not exists (select 1 from sqlite_sequence where name = 'cars_new');
+              update sqlite_sequence
+                  set seq = max(seq, coalesce((select seq from sqlite_sequence
+                                               where name = 'cars'), 0))
+                  where name = 'cars_new';
+              drop table cars;
+              alter table cars_new rename to cars;
+              create index cars_brand on cars (brand, id);
+              create index cars_year on cars (year, id);",
+    },
+    Migration {
+        version: 5,
+        name: "add car vin, color, mileage and timestamps",
+        // sqlite only accepts constant defaults in alter table, existing rows are backfilled
+        sql: "alter table cars add column vin text;
+              alter table cars add column color text;
+              alter table cars add column mileage integer not null default 0;
+              alter table cars add column created_at text;
+              alter table cars add column updated_at text;
+              update cars set created_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'),
+                              updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now');
+              create unique index if not exists cars_vin on cars (vin);",
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, StoreError> {
+    Ok(conn.query_row(
+        "select count(*) > 0 from pragma_table_info(?1) where name=?2",
+        [table, column],
+        |row| row.get(0),
+    )?)
+}
+
+/// Version of a database created before migrations were recorded, guessed from its tables.
+fn legacy_version(conn: &Connection) -> Result<u32, StoreError> {
+    let has_cars: bool = conn.query_row(
+        "select count(*) > 0 from sqlite_master where type='table' and name='cars'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_cars {
+        return Ok(0);
+    }
+    // indexes of version 2 are created with `if not exists`, so replaying them is harmless
+    match has_column(conn, "cars", "revision")? {
+        true => Ok(3),
+        false => Ok(1),
+    }
+}
+
+/// Bring the schema up to [`latest_version`], returning the version it was at before.
+///
+/// Fails without touching anything if the database has been migrated by a newer build.
+pub fn migrate(conn: &mut Connection) -> Result<u32, StoreError> {
+    // an immediate transaction holds the write lock, so concurrent starts migrate only once
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let has_meta: bool = tx.query_row(
+        "select count(*) > 0 from sqlite_master where type='table' and name='schema_migrations'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_meta {
+        let legacy = legacy_version(&tx)?;
+        tx.execute_batch(
+            "create table schema_migrations (
+                 version integer primary key,
+                 name text not null,
+                 applied_at text not null default current_timestamp
+             );",
+        )?;
+        for m in MIGRATIONS.iter().filter(|m| m.version <= legacy) {
+            tx.execute(
+                "insert into schema_migrations (version, name) values (?1, ?2)",
+                rusqlite::params![m.version, m.name],
+            )?;
+        }
+    }
+
+    let current: u32 = tx
+        .query_row("select max(version) from schema_migrations", [], |row| {
+            row.get::<_, Option<u32>>(0)
+        })
+        .optional()?
+        .flatten()
+        .unwrap_or(0);
+    if current > latest_version() {
+        return Err(StoreError::Internal(format!(
+            "database schema version {} is newer than the latest known version {}",
+            current,
+            latest_version()
+        )));
+    }
+
+    for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+        tx.execute_batch(m.sql).map_err(|e| {
+            StoreError::Internal(format!("migration {} ({}): {}", m.version, m.name, e))
+        })?;
+        tx.execute(
+            "insert into schema_migrations (version, name) values (?1, ?2)",
+            rusqlite::params![m.version, m.name],
+        )?;
+        info!("applied migration {} ({})", m.version, m.name);
+    }
+    tx.commit()?;
+    Ok(current)
+}
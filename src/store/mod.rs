@@ -0,0 +1,905 @@
+mod migrations;
+mod patch;
+mod pool;
+mod query;
+mod sqlite;
+mod vin;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{atomic::AtomicU32, RwLock},
+};
+
+pub use self::patch::CarPatch;
+pub use self::query::{CarPage, CarQuery, CarSort, Cursor, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+pub use self::sqlite::{SQLiteCarStore, DEFAULT_DB_PATH, DEFAULT_POOL_SIZE};
+pub use self::vin::normalize_vin;
+
+#[derive(PartialEq, Debug)]
+#[allow(dead_code)]
+pub enum StoreError {
+    NotFound(String),
+    /// the car has been changed since the revision the caller expects
+    PreconditionFailed(String),
+    /// another car already holds the same unique value, e.g. its VIN
+    Conflict(String),
+    Internal(String),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::NotFound(msg)
+            | StoreError::PreconditionFailed(msg)
+            | StoreError::Conflict(msg)
+            | StoreError::Internal(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl StoreError {
+    /// The same error, about the car at `n` in a batch.
+    fn in_batch(self, n: usize) -> StoreError {
+        match self {
+            StoreError::NotFound(msg) => StoreError::NotFound(format!("car {}: {}", n, msg)),
+            StoreError::PreconditionFailed(msg) => {
+                StoreError::PreconditionFailed(format!("car {}: {}", n, msg))
+            }
+            StoreError::Conflict(msg) => StoreError::Conflict(format!("car {}: {}", n, msg)),
+            StoreError::Internal(msg) => StoreError::Internal(format!("car {}: {}", n, msg)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Car {
+    #[serde(default = "default_car_id")] // https://serde.rs/field-attrs.html
+    pub id: u32,
+    pub brand: String,
+    pub model: String,
+    pub year: u16,
+    /// Vehicle Identification Number, unique among all cars
+    pub vin: Option<String>,
+    pub color: Option<String>,
+    #[serde(default)]
+    pub mileage: u32,
+    /// bumped on every update, starting from 1
+    #[serde(default)]
+    pub revision: u32,
+    /// set by the store, values sent by clients are ignored
+    #[serde(default)]
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn default_car_id() -> u32 {
+    0
+}
+
+impl Car {
+    /// Check the fields a client sent, normalizing the VIN to upper case.
+    pub fn validate(&mut self) -> Result<(), String> {
+        if self.year == 0 {
+            return Err("car year must be greater than 0".to_owned());
+        }
+        if let Some(vin) = &self.vin {
+            self.vin = Some(normalize_vin(vin)?);
+        }
+        Ok(())
+    }
+}
+
+/// Fail with [`StoreError::Conflict`] if a car other than `id` already has `vin`.
+fn check_vin(cars: &[Car], id: u32, vin: Option<&str>) -> Result<(), StoreError> {
+    match vin {
+        Some(vin)
+            if cars
+                .iter()
+                .any(|car| car.id != id && car.vin.as_deref() == Some(vin)) =>
+        {
+            Err(StoreError::Conflict(format!(
+                "car with vin={} already exists",
+                vin
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_revision(car: &Car, expected: Option<u32>) -> Result<(), StoreError> {
+    match expected {
+        Some(expected) if expected != car.revision => Err(StoreError::PreconditionFailed(format!(
+            "car with id={} is at revision {}, not {}",
+            car.id, car.revision, expected
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Writes taking an `expected` revision fail with [`StoreError::PreconditionFailed`]
+/// unless the stored car is still at that revision.
+pub trait CarStore {
+    /// Store a new car, ignoring its `id`, `revision` and timestamps, and return its id.
+    fn create_car(&self, car: Car) -> Result<u32, StoreError>;
+    /// Store all the cars as [`CarStore::create_car`] would, or none of them on an error
+    /// naming the car by its position, and return their ids in order.
+    #[allow(dead_code)]
+    fn create_cars(&self, cars: Vec<Car>) -> Result<Vec<u32>, StoreError>;
+    fn update_car(&self, car: Car, expected: Option<u32>) -> Result<Car, StoreError>;
+    /// Update only the fields set in `patch`, returning the patched car.
+    fn patch_car(&self, id: u32, patch: CarPatch, expected: Option<u32>)
+        -> Result<Car, StoreError>;
+    fn get_car(&self, id: u32) -> Result<Car, StoreError>;
+    #[allow(dead_code)]
+    fn get_all_cars(&self) -> Result<Vec<Car>, StoreError>;
+    fn list_cars(&self, query: &CarQuery) -> Result<CarPage, StoreError>;
+    fn delete_car(&self, id: u32, expected: Option<u32>) -> Result<(), StoreError>;
+    fn delete_all_cars(&self) -> Result<(), StoreError>;
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Async counterpart of [`CarStore`], used by the HTTP handlers.
+///
+/// Implementations must not block the calling task, so that a slow store never
+/// stalls the tokio workers which serve hyper connections.
+pub trait AsyncCarStore {
+    fn create_car(&self, car: Car) -> BoxFuture<'_, Result<u32, StoreError>>;
+    fn create_cars(&self, cars: Vec<Car>) -> BoxFuture<'_, Result<Vec<u32>, StoreError>>;
+    fn update_car(&self, car: Car, expected: Option<u32>)
+        -> BoxFuture<'_, Result<Car, StoreError>>;
+    fn patch_car(
+        &self,
+        id: u32,
+        patch: CarPatch,
+        expected: Option<u32>,
+    ) -> BoxFuture<'_, Result<Car, StoreError>>;
+    fn get_car(&self, id: u32) -> BoxFuture<'_, Result<Car, StoreError>>;
+    fn get_all_cars(&self) -> BoxFuture<'_, Result<Vec<Car>, StoreError>>;
+    fn list_cars(&self, query: CarQuery) -> BoxFuture<'_, Result<CarPage, StoreError>>;
+    fn delete_car(&self, id: u32, expected: Option<u32>) -> BoxFuture<'_, Result<(), StoreError>>;
+    fn delete_all_cars(&self) -> BoxFuture<'_, Result<(), StoreError>>;
+}
+
+pub struct MemCarStore {
+    cars: RwLock<Vec<Car>>,
+    next_id: AtomicU32,
+}
+
+impl MemCarStore {
+    pub fn init() -> MemCarStore {
+        let now = Utc::now();
+        MemCarStore {
+            cars: RwLock::new(vec![
+                Car {
+                    id: 1,
+                    brand: "Ford".to_owned(),
+                    model: "Bronco".to_owned(),
+                    year: 2022,
+                    vin: None,
+                    color: None,
+                    mileage: 0,
+                    revision: 1,
+                    created_at: now,
+                    updated_at: now,
+                },
+                Car {
+                    id: 2,
+                    brand: "Hyundai".to_owned(),
+                    model: "Santa Fe".to_owned(),
+                    year: 2010,
+                    vin: None,
+                    color: None,
+                    mileage: 0,
+                    revision: 1,
+                    created_at: now,
+                    updated_at: now,
+                },
+                Car {
+                    id: 3,
+                    brand: "Dodge".to_owned(),
+                    model: "Challenger".to_owned(),
+                    year: 2015,
+                    vin: None,
+                    color: None,
+                    mileage: 0,
+                    revision: 1,
+                    created_at: now,
+                    updated_at: now,
+                },
+            ]),
+            next_id: AtomicU32::new(4),
+        }
+    }
+}
+
+impl CarStore for MemCarStore {
+    fn create_car(&self, car: Car) -> std::result::Result<u32, StoreError> {
+        let mut writer = self.cars.write().unwrap();
+        check_vin(&writer, 0, car.vin.as_deref())?;
+        let id = self
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let now = Utc::now();
+        writer.push(Car {
+            id,
+            revision: 1,
+            created_at: now,
+            updated_at: now,
+            ..car
+        });
+        Ok(id)
+    }
+
+    fn create_cars(&self, cars: Vec<Car>) -> Result<Vec<u32>, StoreError> {
+        let mut writer = self.cars.write().unwrap();
+        for (n, car) in cars.iter().enumerate() {
+            check_vin(&writer, 0, car.vin.as_deref()).map_err(|e| e.in_batch(n))?;
+            if let Some(vin) = car.vin.as_deref() {
+                if cars[..n]
+                    .iter()
+                    .any(|other| other.vin.as_deref() == Some(vin))
+                {
+                    return Err(StoreError::Conflict(format!(
+                        "car with vin={} already exists",
+                        vin
+                    ))
+                    .in_batch(n));
+                }
+            }
+        }
+        let now = Utc::now();
+        let ids = cars
+            .into_iter()
+            .map(|car| {
+                let id = self
+                    .next_id
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                writer.push(Car {
+                    id,
+                    revision: 1,
+                    created_at: now,
+                    updated_at: now,
+                    ..car
+                });
+                id
+            })
+            .collect();
+        Ok(ids)
+    }
+
+    fn update_car(&self, car: Car, expected: Option<u32>) -> Result<Car, StoreError> {
+        let mut writer: std::sync::RwLockWriteGuard<Vec<Car>> = self.cars.write().unwrap();
+        match writer.iter().position(|ocar| ocar.id == car.id) {
+            Some(pos) => {
+                check_revision(&writer[pos], expected)?;
+                check_vin(&writer, car.id, car.vin.as_deref())?;
+                let ocar = &mut writer[pos];
+                ocar.brand = car.brand;
+                ocar.model = car.model;
+                ocar.year = car.year;
+                ocar.vin = car.vin;
+                ocar.color = car.color;
+                ocar.mileage = car.mileage;
+                ocar.revision += 1;
+                ocar.updated_at = Utc::now();
+                Ok(ocar.clone())
+            }
+            None => Err(StoreError::NotFound(format!(
+                "car with id={} not found",
+                car.id
+            ))),
+        }
+    }
+
+    fn patch_car(
+        &self,
+        id: u32,
+        patch: CarPatch,
+        expected: Option<u32>,
+    ) -> Result<Car, StoreError> {
+        let mut writer = self.cars.write().unwrap();
+        match writer.iter().position(|ocar| ocar.id == id) {
+            Some(pos) => {
+                check_revision(&writer[pos], expected)?;
+                if let Some(Some(vin)) = &patch.vin {
+                    check_vin(&writer, id, Some(vin))?;
+                }
+                let ocar = &mut writer[pos];
+                if let Some(brand) = patch.brand {
+                    ocar.brand = brand;
+                }
+                if let Some(model) = patch.model {
+                    ocar.model = model;
+                }
+                if let Some(year) = patch.year {
+                    ocar.year = year;
+                }
+                if let Some(vin) = patch.vin {
+                    ocar.vin = vin;
+                }
+                if let Some(color) = patch.color {
+                    ocar.color = color;
+                }
+                if let Some(mileage) = patch.mileage {
+                    ocar.mileage = mileage;
+                }
+                ocar.revision += 1;
+                ocar.updated_at = Utc::now();
+                Ok(ocar.clone())
+            }
+            None => Err(StoreError::NotFound(format!(
+                "car with id={} not found",
+                id
+            ))),
+        }
+    }
+
+    fn get_car(&self, id: u32) -> Result<Car, StoreError> {
+        let reader = self.cars.read().unwrap();
+        let car = reader.iter().find(|&car| car.id == id).cloned();
+        match car {
+            Some(car) => Ok(car),
+            None => Err(StoreError::NotFound(format!(
+                "car with id={} not found",
+                id
+            ))),
+        }
+    }
+
+    fn get_all_cars(&self) -> std::result::Result<Vec<Car>, StoreError> {
+        let reader = self.cars.read().unwrap();
+        Ok(reader.clone())
+    }
+
+    fn list_cars(&self, query: &CarQuery) -> Result<CarPage, StoreError> {
+        let mut cars: Vec<Car> = {
+            let reader = self.cars.read().unwrap();
+            reader
+                .iter()
+                .filter(|car| query.matches(car))
+                .cloned()
+                .collect()
+        };
+        cars.sort_by(|a, b| query.sort.compare(a, b));
+        cars.truncate(query.limit + 1);
+        Ok(CarPage::new(cars, query))
+    }
+
+    fn delete_car(&self, id: u32, expected: Option<u32>) -> Result<(), StoreError> {
+        let mut writer = self.cars.write().unwrap();
+        match writer.iter().position(|car| car.id == id) {
+            None => Err(StoreError::NotFound(format!(
+                "car with id={} not found",
+                id
+            ))),
+            Some(idx) => {
+                check_revision(&writer[idx], expected)?;
+                writer.remove(idx);
+                Ok(())
+            }
+        }
+    }
+
+    fn delete_all_cars(&self) -> Result<(), StoreError> {
+        let mut writer = self.cars.write().unwrap();
+        _ = mem::take(&mut *writer);
+        self.next_id.store(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+// in-memory operations never wait on IO, so run them inline
+impl AsyncCarStore for MemCarStore {
+    fn create_car(&self, car: Car) -> BoxFuture<'_, Result<u32, StoreError>> {
+        Box::pin(async move { CarStore::create_car(self, car) })
+    }
+
+    fn create_cars(&self, cars: Vec<Car>) -> BoxFuture<'_, Result<Vec<u32>, StoreError>> {
+        Box::pin(async move { CarStore::create_cars(self, cars) })
+    }
+
+    fn update_car(
+        &self,
+        car: Car,
+        expected: Option<u32>,
+    ) -> BoxFuture<'_, Result<Car, StoreError>> {
+        Box::pin(async move { CarStore::update_car(self, car, expected) })
+    }
+
+    fn patch_car(
+        &self,
+        id: u32,
+        patch: CarPatch,
+        expected: Option<u32>,
+    ) -> BoxFuture<'_, Result<Car, StoreError>> {
+        Box::pin(async move { CarStore::patch_car(self, id, patch, expected) })
+    }
+
+    fn get_car(&self, id: u32) -> BoxFuture<'_, Result<Car, StoreError>> {
+        Box::pin(async move { CarStore::get_car(self, id) })
+    }
+
+    fn get_all_cars(&self) -> BoxFuture<'_, Result<Vec<Car>, StoreError>> {
+        Box::pin(async move { CarStore::get_all_cars(self) })
+    }
+
+    fn list_cars(&self, query: CarQuery) -> BoxFuture<'_, Result<CarPage, StoreError>> {
+        Box::pin(async move { CarStore::list_cars(self, &query) })
+    }
+
+    fn delete_car(&self, id: u32, expected: Option<u32>) -> BoxFuture<'_, Result<(), StoreError>> {
+        Box::pin(async move { CarStore::delete_car(self, id, expected) })
+    }
+
+    fn delete_all_cars(&self) -> BoxFuture<'_, Result<(), StoreError>> {
+        Box::pin(async move { CarStore::delete_all_cars(self) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        migrations, normalize_vin, Car, CarPatch, CarQuery, CarSort, CarStore, Cursor, MemCarStore,
+        SQLiteCarStore, StoreError,
+    };
+    use crate::store::sqlite::MEMORY_DB_PATH;
+
+    fn new_car(brand: &str, model: &str, year: u16) -> Car {
+        serde_json::from_value(serde_json::json!({
+            "brand": brand,
+            "model": model,
+            "year": year,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_create_car() {
+        let sqlcars = SQLiteCarStore::open(MEMORY_DB_PATH, 1).unwrap();
+        let nid = sqlcars
+            .create_car(new_car("BYD", "Han", 2020))
+            .expect("should return new row id in cars table");
+        sqlcars
+            .get_car(nid)
+            .expect("should return the new created car");
+        sqlcars.get_all_cars().expect("list cars should be ok");
+        sqlcars
+            .delete_car(nid, None)
+            .expect("delete the new created car");
+    }
+
+    #[test]
+    fn test_delete_car() {
+        let sqlcars = SQLiteCarStore::open(MEMORY_DB_PATH, 1).unwrap();
+        println!(
+            "{:?}{:?}",
+            sqlcars.create_car(new_car("BYD", "Han", 2020)),
+            sqlcars.create_car(new_car("Tesla", "Mode X", 2015))
+        );
+        let cars = sqlcars.get_all_cars().expect("list car should be ok");
+        assert_eq!(cars.len(), 2);
+        sqlcars
+            .delete_all_cars()
+            .expect("delete all cars should be ok");
+        assert!(sqlcars.get_all_cars().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_sqlite_car_store() {
+        let path = temp_db_path("cars-async");
+        let sqlcars = SQLiteCarStore::open(path.to_str().unwrap(), 2).unwrap();
+        let nid = super::AsyncCarStore::create_car(&sqlcars, new_car("BYD", "Seal", 2023))
+            .await
+            .expect("should return new row id in cars table");
+        let car = super::AsyncCarStore::get_car(&sqlcars, nid)
+            .await
+            .expect("should return the new created car");
+        assert_eq!(car.model, "Seal");
+        super::AsyncCarStore::delete_car(&sqlcars, nid, None)
+            .await
+            .expect("delete the new created car");
+        drop(sqlcars);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sqlite_memory_uri() {
+        for path in [
+            MEMORY_DB_PATH,
+            "file::memory:",
+            "file::memory:?cache=shared",
+            "file:cars-test?mode=memory&cache=shared",
+        ] {
+            let sqlcars = SQLiteCarStore::open(path, 4).unwrap();
+            // every query sees the migrated database, whichever connection runs it
+            let ids =
+                futures_util::future::try_join_all((0..16).map(|_| {
+                    super::AsyncCarStore::create_car(&sqlcars, new_car("BYD", "Han", 2020))
+                }))
+                .await
+                .unwrap_or_else(|e| panic!("{}: {}", path, e));
+            let cars = super::AsyncCarStore::get_all_cars(&sqlcars).await.unwrap();
+            assert_eq!(cars.len(), ids.len(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_mem_car_store() {
+        let memcars = MemCarStore::init();
+        let nid = super::AsyncCarStore::create_car(&memcars, new_car("BYD", "Seal", 2023))
+            .await
+            .expect("should return new id");
+        let cars = super::AsyncCarStore::get_all_cars(&memcars)
+            .await
+            .expect("list cars should be ok");
+        assert!(cars.iter().any(|car| car.id == nid));
+    }
+
+    #[test]
+    fn test_list_cars_paginated() {
+        let memcars = MemCarStore::init();
+        let sqlcars = SQLiteCarStore::open(MEMORY_DB_PATH, 1).unwrap();
+        sqlcars.create_car(new_car("Ford", "Bronco", 2022)).unwrap();
+
+        let stores: [&dyn CarStore; 2] = [&memcars, &sqlcars];
+        for cars in stores {
+            cars.create_car(new_car("Ford", "Mustang", 2015)).unwrap();
+            cars.create_car(new_car("Ford", "Focus", 2018)).unwrap();
+
+            let sort: CarSort = "-year".parse().unwrap();
+            let mut query = CarQuery {
+                brand: Some("Ford".to_owned()),
+                year_gte: Some(2015),
+                sort,
+                limit: 2,
+                ..Default::default()
+            };
+            let page = cars.list_cars(&query).expect("list cars should be ok");
+            let years: Vec<u16> = page.items.iter().map(|car| car.year).collect();
+            assert_eq!(years, vec![2022, 2018]);
+
+            let cursor = page.next_cursor.expect("should have a next page");
+            query.cursor = Some(Cursor::decode(&cursor, &sort).unwrap());
+            let page = cars.list_cars(&query).expect("list cars should be ok");
+            assert_eq!(page.items.len(), 1);
+            assert_eq!(page.items[0].model, "Mustang");
+            assert!(page.next_cursor.is_none());
+
+            assert!(Cursor::decode(&cursor, &CarSort::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_patch_car() {
+        let memcars = MemCarStore::init();
+        let patch = CarPatch::from_merge_patch(&serde_json::json!({ "year": 2023 })).unwrap();
+        let car = memcars
+            .patch_car(1, patch, None)
+            .expect("patch car should be ok");
+        assert_eq!((car.model.as_str(), car.year), ("Bronco", 2023));
+
+        let patch = CarPatch::from_json_patch(&serde_json::json!([
+            { "op": "replace", "path": "/model", "value": "Maverick" }
+        ]))
+        .unwrap();
+        let car = memcars
+            .patch_car(1, patch, Some(2))
+            .expect("patch car should be ok");
+        assert_eq!((car.model.as_str(), car.year), ("Maverick", 2023));
+        assert_eq!(car.revision, 3);
+
+        assert!(CarPatch::from_merge_patch(&serde_json::json!({ "brand": null })).is_err());
+        assert!(memcars.patch_car(100, CarPatch::default(), None).is_err());
+    }
+
+    #[test]
+    fn test_update_car_revision() {
+        let memcars = MemCarStore::init();
+        let mut car = memcars.get_car(2).unwrap();
+        car.year = 2011;
+        let updated = memcars
+            .update_car(car.clone(), Some(car.revision))
+            .expect("update at the current revision should be ok");
+        assert_eq!(updated.revision, car.revision + 1);
+        assert!(matches!(
+            memcars.update_car(car.clone(), Some(car.revision)),
+            Err(StoreError::PreconditionFailed(_))
+        ));
+        assert!(matches!(
+            memcars.delete_car(2, Some(car.revision)),
+            Err(StoreError::PreconditionFailed(_))
+        ));
+        memcars
+            .delete_car(2, Some(updated.revision))
+            .expect("delete at the current revision should be ok");
+    }
+
+    #[test]
+    fn test_delete_missing_car() {
+        let memcars = MemCarStore::init();
+        let sqlcars = SQLiteCarStore::open(MEMORY_DB_PATH, 1).unwrap();
+        let stores: [&dyn CarStore; 2] = [&memcars, &sqlcars];
+        for cars in stores {
+            let id = cars.create_car(new_car("BYD", "Han", 2020)).unwrap();
+            cars.delete_car(id, None).unwrap();
+            assert!(matches!(
+                cars.delete_car(id, None),
+                Err(StoreError::NotFound(_))
+            ));
+            assert!(matches!(
+                cars.delete_car(id, Some(1)),
+                Err(StoreError::NotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_car_vin() {
+        assert_eq!(
+            normalize_vin("1m8gdm9axkp042788").unwrap(),
+            "1M8GDM9AXKP042788"
+        );
+        assert!(normalize_vin("1M8GDM9A1KP042788").is_err());
+        assert!(normalize_vin("1M8GDM9AXKP04278O").is_err());
+        assert!(normalize_vin("1M8GDM9AX").is_err());
+
+        let memcars = MemCarStore::init();
+        let sqlcars = SQLiteCarStore::open(MEMORY_DB_PATH, 1).unwrap();
+        let stores: [&dyn CarStore; 2] = [&memcars, &sqlcars];
+        for cars in stores {
+            let mut car = new_car("Ford", "Bronco", 2022);
+            car.vin = Some("1M8GDM9AXKP042788".to_owned());
+            car.mileage = 1200;
+            let id = cars.create_car(car.clone()).unwrap();
+            let created = cars.get_car(id).unwrap();
+            assert_eq!(created.vin, car.vin);
+            assert_eq!(created.mileage, 1200);
+            assert_eq!(created.created_at, created.updated_at);
+
+            assert!(matches!(
+                cars.create_car(car.clone()),
+                Err(StoreError::Conflict(_))
+            ));
+            // a missing car is not found, whatever its vin
+            let missing = Car {
+                id: 999,
+                ..car.clone()
+            };
+            assert!(matches!(
+                cars.update_car(missing, None),
+                Err(StoreError::NotFound(_))
+            ));
+            car.vin = None;
+            let other = cars.create_car(car.clone()).unwrap();
+            let patch = CarPatch::from_merge_patch(
+                &serde_json::json!({ "vin": "1m8gdm9axkp042788", "color": "red" }),
+            )
+            .unwrap();
+            assert!(matches!(
+                cars.patch_car(999, patch.clone(), None),
+                Err(StoreError::NotFound(_))
+            ));
+            assert!(matches!(
+                cars.patch_car(other, patch.clone(), Some(7)),
+                Err(StoreError::PreconditionFailed(_))
+            ));
+            assert!(matches!(
+                cars.patch_car(other, patch, None),
+                Err(StoreError::Conflict(_))
+            ));
+
+            let patch = CarPatch::from_merge_patch(&serde_json::json!({ "vin": null })).unwrap();
+            let patched = cars.patch_car(id, patch, None).unwrap();
+            assert_eq!(patched.vin, None);
+            assert!(patched.updated_at >= created.updated_at);
+            assert_eq!(patched.created_at, created.created_at);
+        }
+    }
+
+    #[test]
+    fn test_create_cars() {
+        let memcars = MemCarStore::init();
+        let sqlcars = SQLiteCarStore::open(MEMORY_DB_PATH, 1).unwrap();
+        let stores: [&dyn CarStore; 2] = [&memcars, &sqlcars];
+        for cars in stores {
+            let before = cars.get_all_cars().unwrap().len();
+            let mut car = new_car("Ford", "Bronco", 2022);
+            car.vin = Some("1M8GDM9AXKP042788".to_owned());
+            let ids = cars
+                .create_cars(vec![new_car("BYD", "Han", 2020), car.clone()])
+                .unwrap();
+            assert_eq!(ids.len(), 2);
+            assert_eq!(cars.get_car(ids[1]).unwrap().vin, car.vin);
+
+            // a duplicate of a stored car or of one earlier in the batch stores nothing
+            let mut other = new_car("Ford", "F-150", 2013);
+            other.vin = Some("1FTFW1ET5DFC10312".to_owned());
+            for (batch, at) in [
+                (vec![new_car("BYD", "Seal", 2023), car.clone()], "car 1: "),
+                (
+                    vec![new_car("BYD", "Seal", 2023), other.clone(), other],
+                    "car 2: ",
+                ),
+            ] {
+                match cars.create_cars(batch) {
+                    Err(StoreError::Conflict(e)) => assert!(e.starts_with(at), "{}", e),
+                    other => panic!("{:?}", other),
+                }
+            }
+            assert_eq!(cars.get_all_cars().unwrap().len(), before + 2);
+        }
+    }
+
+    #[test]
+    fn test_get_all_cars_unreadable() {
+        let path = temp_db_path("cars-unreadable");
+        let sqlcars = SQLiteCarStore::open(path.to_str().unwrap(), 1).unwrap();
+        sqlcars.create_car(new_car("BYD", "Han", 2020)).unwrap();
+        let id = sqlcars.create_car(new_car("Ford", "Bronco", 2022)).unwrap();
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute("update cars set year='soon' where id=?", [id])
+            .unwrap();
+        match sqlcars.get_all_cars() {
+            Err(StoreError::Internal(e)) => assert!(e.contains(&format!("id={}", id)), "{}", e),
+            other => panic!("{:?}", other),
+        }
+        drop(sqlcars);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unique_violation() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "create table cars (vin text unique, plate text unique);
+             insert into cars values ('1M8GDM9AXKP042788', 'B-1');",
+        )
+        .unwrap();
+        let insert = |vin: &str, plate: &str| -> StoreError {
+            conn.execute("insert into cars values (?1, ?2)", [vin, plate])
+                .unwrap_err()
+                .into()
+        };
+        assert!(matches!(
+            insert("1M8GDM9AXKP042788", "B-2"),
+            StoreError::Conflict(_)
+        ));
+        // another unique index is not taken for a duplicate vin
+        assert!(matches!(
+            insert("1FTFW1ET5DFC10312", "B-1"),
+            StoreError::Internal(_)
+        ));
+    }
+
+    fn temp_db_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_migrate_legacy_database() {
+        let path = temp_db_path("cars-legacy");
+        let mut conn = rusqlite::Connection::open(&path).unwrap();
+        // the schema SQLiteCarStore used to create before migrations
+        conn.execute_batch(
+            "create table cars (
+                 id integer primary key autoincrement,
+                 brand text not null,
+                 model text not null,
+                 year integer
+             );
+             insert into cars (brand, model, year) values ('BYD', 'Han', 2020);
+             insert into cars (brand, model, year) values ('Ford', 'Model T', null);
+             insert into cars (brand, model, year) values ('Tesla', 'Model X', 2015);
+             delete from cars where id=3;",
+        )
+        .unwrap();
+
+        // a car without a year is left for the operator to fix, nothing is migrated
+        match migrations::migrate(&mut conn) {
+            Err(StoreError::Internal(e)) => assert!(e.contains("year"), "{}", e),
+            other => panic!("{:?}", other),
+        }
+        let migrated: bool = conn
+            .query_row(
+                "select count(*) > 0 from sqlite_master where name='schema_migrations'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!migrated);
+        conn.execute("update cars set year=1908 where id=2", [])
+            .unwrap();
+
+        assert_eq!(migrations::migrate(&mut conn).unwrap(), 1);
+        let (model, revision): (String, u32) = conn
+            .query_row("select model, revision from cars where id=1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((model.as_str(), revision), ("Han", 1));
+        let versions: Vec<u32> = conn
+            .prepare("select version from schema_migrations order by version")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(versions, vec![1, 2, 3, 4, 5]);
+        assert!(conn
+            .execute("insert into cars (brand, model) values ('BYD', 'Seal')", [])
+            .is_err());
+        // the id of the deleted car is not handed out again
+        let id = conn
+            .query_row(
+                "insert into cars (brand, model, year) values ('BYD', 'Seal', 2023) returning id",
+                [],
+                |row| row.get::<_, u32>(0),
+            )
+            .unwrap();
+        assert_eq!(id, 4);
+
+        // migrating twice is a no-op
+        assert_eq!(
+            migrations::migrate(&mut conn).unwrap(),
+            migrations::latest_version()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_migrate_emptied_database() {
+        let path = temp_db_path("cars-emptied");
+        let mut conn = rusqlite::Connection::open(&path).unwrap();
+        // a legacy database whose cars have all been deleted
+        conn.execute_batch(
+            "create table cars (
+                 id integer primary key autoincrement,
+                 brand text not null,
+                 model text not null,
+                 year integer
+             );
+             insert into cars (brand, model, year) values ('BYD', 'Han', 2020);
+             insert into cars (brand, model, year) values ('Tesla', 'Model X', 2015);
+             delete from cars;",
+        )
+        .unwrap();
+
+        assert_eq!(migrations::migrate(&mut conn).unwrap(), 1);
+        let id = conn
+            .query_row(
+                "insert into cars (brand, model, year) values ('BYD', 'Seal', 2023) returning id",
+                [],
+                |row| row.get::<_, u32>(0),
+            )
+            .unwrap();
+        assert_eq!(id, 3);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer_schema() {
+        let path = temp_db_path("cars-newer");
+        let mut conn = rusqlite::Connection::open(&path).unwrap();
+        migrations::migrate(&mut conn).unwrap();
+        conn.execute(
+            "insert into schema_migrations (version, name) values (?1, 'from the future')",
+            [migrations::latest_version() + 1],
+        )
+        .unwrap();
+        assert!(matches!(
+            migrations::migrate(&mut conn),
+            Err(StoreError::Internal(_))
+        ));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,98 @@
+use super::normalize_vin;
+use serde_json::{Map, Value};
+
+/// Partial update of a car, `None` fields are left untouched.
+///
+/// Optional car fields are cleared by `Some(None)`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CarPatch {
+    pub brand: Option<String>,
+    pub model: Option<String>,
+    pub year: Option<u16>,
+    pub vin: Option<Option<String>>,
+    pub color: Option<Option<String>>,
+    pub mileage: Option<u32>,
+}
+
+impl CarPatch {
+    fn set(&mut self, field: &str, value: &Value) -> Result<(), String> {
+        match (field, value) {
+            ("brand", Value::String(s)) => self.brand = Some(s.clone()),
+            ("model", Value::String(s)) => self.model = Some(s.clone()),
+            ("year", Value::Number(n)) => {
+                let year = n
+                    .as_u64()
+                    .and_then(|n| u16::try_from(n).ok())
+                    .ok_or_else(|| format!("invalid year={}, expect uint16 number", n))?;
+                if year == 0 {
+                    return Err("car year must be greater than 0".to_owned());
+                }
+                self.year = Some(year);
+            }
+            ("vin", Value::String(s)) => self.vin = Some(Some(normalize_vin(s)?)),
+            ("color", Value::String(s)) => self.color = Some(Some(s.clone())),
+            ("vin", Value::Null) => self.vin = Some(None),
+            ("color", Value::Null) => self.color = Some(None),
+            ("mileage", Value::Number(n)) => {
+                let mileage = n
+                    .as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| format!("invalid mileage={}, expect uint32 number", n))?;
+                self.mileage = Some(mileage);
+            }
+            ("brand" | "model" | "year" | "mileage", Value::Null) => {
+                return Err(format!("{} is required and cannot be removed", field))
+            }
+            ("brand" | "model" | "vin" | "color", v) => {
+                return Err(format!("invalid {}={}, expect string", field, v))
+            }
+            ("year", v) => return Err(format!("invalid year={}, expect uint16 number", v)),
+            ("mileage", v) => return Err(format!("invalid mileage={}, expect uint32 number", v)),
+            ("id" | "revision" | "created_at" | "updated_at", _) => {
+                return Err(format!("{} cannot be patched", field))
+            }
+            (field, _) => return Err(format!("unknown field {}", field)),
+        }
+        Ok(())
+    }
+
+    /// Parse a JSON Merge Patch (RFC 7396) document, e.g. `{"year": 2020}`.
+    pub fn from_merge_patch(doc: &Value) -> Result<CarPatch, String> {
+        let members: &Map<String, Value> =
+            doc.as_object().ok_or("merge patch must be a JSON object")?;
+        let mut patch = CarPatch::default();
+        for (field, value) in members {
+            patch.set(field, value)?;
+        }
+        Ok(patch)
+    }
+
+    /// Parse a JSON Patch (RFC 6902) document, e.g. `[{"op": "replace", "path": "/year", "value": 2020}]`.
+    ///
+    /// Only `add` and `replace` are supported, car fields can neither be removed nor moved.
+    pub fn from_json_patch(doc: &Value) -> Result<CarPatch, String> {
+        let ops = doc.as_array().ok_or("json patch must be a JSON array")?;
+        let mut patch = CarPatch::default();
+        for op in ops {
+            let name = op.get("op").and_then(Value::as_str).unwrap_or_default();
+            let path = op
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or("json patch operation requires a path")?;
+            let field = path
+                .strip_prefix('/')
+                .filter(|field| !field.contains('/'))
+                .ok_or_else(|| format!("invalid path={}", path))?;
+            match name {
+                "add" | "replace" => {
+                    let value = op
+                        .get("value")
+                        .ok_or_else(|| format!("{} operation requires a value", name))?;
+                    patch.set(field, value)?;
+                }
+                _ => return Err(format!("unsupported json patch operation {:?}", name)),
+            }
+        }
+        Ok(patch)
+    }
+}
@@ -0,0 +1,82 @@
+use super::StoreError;
+use rusqlite::Connection;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// A fixed set of worker threads, each owning one SQLite connection.
+///
+/// Queries are shipped to the workers as closures, so a slow disk only ever
+/// blocks these threads and never the tokio workers serving connections.
+/// The number of workers is also the upper bound of open connections.
+pub struct ConnPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl ConnPool {
+    /// Start one worker per connection.
+    pub fn new(conns: Vec<Connection>) -> Result<ConnPool, StoreError> {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for (i, mut conn) in conns.into_iter().enumerate() {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("sqlite-worker-{}", i))
+                .spawn(move || loop {
+                    // the lock is released as soon as a job has been received
+                    let job = match rx.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        // all senders are gone, the pool has been dropped
+                        Err(_) => return,
+                    };
+                    job(&mut conn);
+                })
+                .map_err(|e| StoreError::Internal(e.to_string()))?;
+        }
+        Ok(ConnPool {
+            jobs: Mutex::new(tx),
+        })
+    }
+
+    fn submit<T, F>(&self, f: F) -> Result<tokio::sync::oneshot::Receiver<T>, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> T + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let job: Job = Box::new(move |conn| {
+            let _ = tx.send(f(conn));
+        });
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(job)
+            .map_err(|_| StoreError::Internal("sqlite workers are gone".to_owned()))?;
+        Ok(rx)
+    }
+
+    /// Run `f` on a pooled connection and wait for it asynchronously.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+    {
+        let rx = self.submit(f)?;
+        rx.await
+            .map_err(|_| StoreError::Internal("sqlite worker dropped the query".to_owned()))?
+    }
+
+    /// Run `f` on a pooled connection, blocking the current thread until it is done.
+    ///
+    /// Must not be called from within an async context.
+    pub fn run_blocking<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+    {
+        let rx = self.submit(f)?;
+        rx.blocking_recv()
+            .map_err(|_| StoreError::Internal("sqlite worker dropped the query".to_owned()))?
+    }
+}
@@ -0,0 +1,209 @@
+use super::Car;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SortField {
+    #[default]
+    Id,
+    Brand,
+    Model,
+    Year,
+}
+
+impl SortField {
+    /// column name in the cars table
+    pub fn column(&self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Brand => "brand",
+            SortField::Model => "model",
+            SortField::Year => "year",
+        }
+    }
+}
+
+/// Sort order of a car listing, parsed from `year` (ascending) or `-year` (descending).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct CarSort {
+    pub field: SortField,
+    pub desc: bool,
+}
+
+impl CarSort {
+    pub fn key(&self, car: &Car) -> SortKey {
+        match self.field {
+            SortField::Id => SortKey::Int(car.id),
+            SortField::Brand => SortKey::Text(car.brand.clone()),
+            SortField::Model => SortKey::Text(car.model.clone()),
+            SortField::Year => SortKey::Int(car.year as u32),
+        }
+    }
+
+    /// Compare by the sort field, ties are broken by id in the same direction.
+    pub fn compare(&self, a: &Car, b: &Car) -> Ordering {
+        let ord = (self.key(a), a.id).cmp(&(self.key(b), b.id));
+        if self.desc {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+}
+
+impl FromStr for CarSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (desc, name) = match s.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let field = match name {
+            "id" => SortField::Id,
+            "brand" => SortField::Brand,
+            "model" => SortField::Model,
+            "year" => SortField::Year,
+            _ => {
+                return Err(format!(
+                "invalid sort={}, expect one of id, brand, model, year with optional '-' prefix",
+                s
+            ))
+            }
+        };
+        Ok(CarSort { field, desc })
+    }
+}
+
+impl fmt::Display for CarSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.desc {
+            write!(f, "-{}", self.field.column())
+        } else {
+            f.write_str(self.field.column())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum SortKey {
+    Int(u32),
+    Text(String),
+}
+
+/// Position of the last car of a page, the next page starts right after it.
+///
+/// Cursors are opaque to clients: URL-safe base64 of a small JSON document.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cursor {
+    #[serde(rename = "s")]
+    sort: String,
+    #[serde(rename = "k")]
+    pub key: SortKey,
+    pub id: u32,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    /// Decode a cursor issued for the same sort order.
+    pub fn decode(s: &str, sort: &CarSort) -> Result<Cursor, String> {
+        let cursor = URL_SAFE_NO_PAD
+            .decode(s)
+            .ok()
+            .and_then(|raw| serde_json::from_slice::<Cursor>(&raw).ok())
+            .ok_or_else(|| format!("invalid cursor={}", s))?;
+        if cursor.sort != sort.to_string() {
+            return Err(format!(
+                "cursor was issued for sort={}, not sort={}",
+                cursor.sort, sort
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CarQuery {
+    pub brand: Option<String>,
+    pub model: Option<String>,
+    pub year_gte: Option<u16>,
+    pub year_lte: Option<u16>,
+    pub sort: CarSort,
+    pub limit: usize,
+    pub cursor: Option<Cursor>,
+}
+
+impl Default for CarQuery {
+    fn default() -> Self {
+        CarQuery {
+            brand: None,
+            model: None,
+            year_gte: None,
+            year_lte: None,
+            sort: CarSort::default(),
+            limit: DEFAULT_PAGE_LIMIT,
+            cursor: None,
+        }
+    }
+}
+
+impl CarQuery {
+    /// Whether `car` passes the filters and lies after the cursor.
+    pub fn matches(&self, car: &Car) -> bool {
+        if matches!(&self.brand, Some(brand) if brand != &car.brand)
+            || matches!(&self.model, Some(model) if model != &car.model)
+            || matches!(self.year_gte, Some(year) if car.year < year)
+            || matches!(self.year_lte, Some(year) if car.year > year)
+        {
+            return false;
+        }
+        match &self.cursor {
+            Some(cursor) => {
+                let ord = (self.sort.key(car), car.id).cmp(&(cursor.key.clone(), cursor.id));
+                if self.sort.desc {
+                    ord == Ordering::Less
+                } else {
+                    ord == Ordering::Greater
+                }
+            }
+            None => true,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CarPage {
+    pub items: Vec<Car>,
+    pub next_cursor: Option<String>,
+}
+
+impl CarPage {
+    /// Build a page from up to `limit + 1` sorted cars, the extra one only tells there is a next page.
+    pub fn new(mut cars: Vec<Car>, query: &CarQuery) -> CarPage {
+        let next_cursor = if cars.len() > query.limit {
+            cars.truncate(query.limit);
+            cars.last().map(|last| {
+                Cursor {
+                    sort: query.sort.to_string(),
+                    key: query.sort.key(last),
+                    id: last.id,
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        CarPage {
+            items: cars,
+            next_cursor,
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use super::migrations;
+use super::pool::ConnPool;
+use super::query::SortKey;
+use super::{AsyncCarStore, BoxFuture, Car, CarPage, CarPatch, CarQuery, CarStore, StoreError};
+use chrono::Utc;
+use rusqlite::{ffi, types::Value, Connection, ErrorCode, OptionalExtension, Result, Row};
+
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+const CAR_COLUMNS: &str = "id,brand,model,year,vin,color,mileage,revision,created_at,updated_at";
+
+pub struct SQLiteCarStore {
+    pool: ConnPool,
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        match &e {
+            // sqlite names the columns of the violated index, e.g. `cars.vin`
+            rusqlite::Error::SqliteFailure(err, Some(msg))
+                if err.code == ErrorCode::ConstraintViolation
+                    && err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE
+                    && msg.ends_with(": cars.vin") =>
+            {
+                Self::Conflict("a car with the same vin already exists".to_owned())
+            }
+            _ => Self::Internal(e.to_string()),
+        }
+    }
+}
+
+pub const DEFAULT_DB_PATH: &str = "cars.db";
+
+/// Path of an in-memory database, which lives as long as the store.
+pub const MEMORY_DB_PATH: &str = ":memory:";
+
+/// Whether `path` opens an in-memory database: `:memory:`, `file::memory:` or a `file:` URI
+/// with `mode=memory`.
+fn is_memory(path: &str) -> bool {
+    if path == MEMORY_DB_PATH {
+        return true;
+    }
+    let Some(uri) = path.strip_prefix("file:") else {
+        return false;
+    };
+    let (name, query) = uri.split_once('?').unwrap_or((uri, ""));
+    name == MEMORY_DB_PATH || query.split('&').any(|param| param == "mode=memory")
+}
+
+impl SQLiteCarStore {
+    fn dbconn(path: &str) -> Result<Connection, StoreError> {
+        let conn = Connection::open(path)?;
+        // several pooled connections share one file, wait for locks instead of failing fast
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        Ok(conn)
+    }
+
+    /// Open the database at `path`, migrate it to the latest schema and start `pool_size`
+    /// worker connections.
+    ///
+    /// `path` is a file path, a `file:` URI such as `file:cars.db?mode=rwc`, or `:memory:`.
+    /// Every connection to an in-memory database is a distinct empty database unless it is
+    /// opened with `cache=shared`, so an in-memory store is served by a single connection
+    /// whatever `pool_size` is.
+    pub fn open(path: &str, pool_size: usize) -> Result<SQLiteCarStore, StoreError> {
+        let pool_size = match is_memory(path) {
+            true => 1,
+            false => pool_size.max(1),
+        };
+        let mut first = Self::dbconn(path)?;
+        migrations::migrate(&mut first)?;
+        let mut conns = vec![first];
+        for _ in 1..pool_size {
+            conns.push(Self::dbconn(path)?);
+        }
+        Ok(SQLiteCarStore {
+            pool: ConnPool::new(conns)?,
+        })
+    }
+
+    /// Migrate the database at `path` to the latest schema without opening a store, returning
+    /// the versions it was at before and is at now.
+    pub fn migrate(path: &str) -> Result<(u32, u32), StoreError> {
+        let mut conn = Self::dbconn(path)?;
+        let before = migrations::migrate(&mut conn)?;
+        Ok((before, migrations::latest_version()))
+    }
+}
+
+fn car_from_row(row: &Row) -> Result<Car> {
+    Ok(Car {
+        id: row.get(0)?,
+        brand: row.get(1)?,
+        model: row.get(2)?,
+        year: row.get(3)?,
+        vin: row.get(4)?,
+        color: row.get(5)?,
+        mileage: row.get(6)?,
+        revision: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+/// Tell apart a missing car from a revision mismatch once a conditional write matched no row.
+fn write_missed(conn: &mut Connection, id: u32, expected: Option<u32>) -> StoreError {
+    let revision: Option<u32> = match conn
+        .query_row("SELECT revision FROM cars WHERE id=?", [id], |row| {
+            row.get(0)
+        })
+        .optional()
+    {
+        Ok(revision) => revision,
+        Err(e) => return e.into(),
+    };
+    match (revision, expected) {
+        (Some(revision), Some(expected)) => StoreError::PreconditionFailed(format!(
+            "car with id={} is at revision {}, not {}",
+            id, revision, expected
+        )),
+        _ => StoreError::NotFound(format!("car with id={} not found", id)),
+    }
+}
+
+fn create_car(conn: &Connection, car: Car) -> Result<u32, StoreError> {
+    conn.execute(
+        "INSERT INTO cars (brand,model,year,vin,color,mileage,created_at,updated_at)
+         values (?1,?2,?3,?4,?5,?6,?7,?7)",
+        rusqlite::params![
+            car.brand,
+            car.model,
+            car.year,
+            car.vin,
+            car.color,
+            car.mileage,
+            Utc::now()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid().try_into().unwrap())
+}
+
+fn create_cars(conn: &mut Connection, cars: Vec<Car>) -> Result<Vec<u32>, StoreError> {
+    // dropping the transaction on an error rolls back the cars inserted before
+    let tx = conn.transaction()?;
+    let ids = cars
+        .into_iter()
+        .enumerate()
+        .map(|(n, car)| create_car(&tx, car).map_err(|e| e.in_batch(n)))
+        .collect::<Result<Vec<u32>, _>>()?;
+    tx.commit()?;
+    Ok(ids)
+}
+
+fn update_car(conn: &mut Connection, car: Car, expected: Option<u32>) -> Result<Car, StoreError> {
+    let updated = conn
+        .query_row(
+            &format!(
+                "UPDATE cars SET brand=?1,model=?2,year=?3,vin=?6,color=?7,mileage=?8,
+                 revision=revision+1,updated_at=?9
+                 WHERE id=?4 AND (?5 IS NULL OR revision=?5) RETURNING {CAR_COLUMNS}"
+            ),
+            rusqlite::params![
+                car.brand,
+                car.model,
+                car.year,
+                car.id,
+                expected,
+                car.vin,
+                car.color,
+                car.mileage,
+                Utc::now()
+            ],
+            car_from_row,
+        )
+        .optional()?;
+    match updated {
+        Some(car) => Ok(car),
+        None => Err(write_missed(conn, car.id, expected)),
+    }
+}
+
+fn patch_car(
+    conn: &mut Connection,
+    id: u32,
+    patch: CarPatch,
+    expected: Option<u32>,
+) -> Result<Car, StoreError> {
+    // a single statement, so concurrent patches of different fields never lose each other
+    let patched = conn
+        .query_row(
+            &format!(
+                "UPDATE cars SET brand=coalesce(?1,brand),model=coalesce(?2,model),
+                 year=coalesce(?3,year),mileage=coalesce(?10,mileage),
+                 vin=CASE WHEN ?6 THEN ?7 ELSE vin END,
+                 color=CASE WHEN ?8 THEN ?9 ELSE color END,
+                 revision=revision+1,updated_at=?11
+                 WHERE id=?4 AND (?5 IS NULL OR revision=?5) RETURNING {CAR_COLUMNS}"
+            ),
+            rusqlite::params![
+                patch.brand,
+                patch.model,
+                patch.year,
+                id,
+                expected,
+                // vin and color may be cleared, so whether they are set is passed on its own
+                patch.vin.is_some(),
+                patch.vin.flatten(),
+                patch.color.is_some(),
+                patch.color.flatten(),
+                patch.mileage,
+                Utc::now()
+            ],
+            car_from_row,
+        )
+        .optional()?;
+    match patched {
+        Some(car) => Ok(car),
+        None => Err(write_missed(conn, id, expected)),
+    }
+}
+
+fn get_car(conn: &mut Connection, id: u32) -> Result<Car, StoreError> {
+    let car = conn
+        .query_row(
+            &format!("SELECT {CAR_COLUMNS} FROM cars where id=?"),
+            [id],
+            car_from_row,
+        )
+        .optional()?;
+    match car {
+        Some(car) => Ok(car),
+        None => Err(StoreError::NotFound(format!(
+            "car with id={} not found",
+            id
+        ))),
+    }
+}
+
+fn get_all_cars(conn: &mut Connection) -> Result<Vec<Car>, StoreError> {
+    let mut stmt = conn.prepare(&format!("SELECT {CAR_COLUMNS} FROM cars ORDER BY id"))?;
+    let car_iter = stmt.query_map([], |row| {
+        Ok(car_from_row(row).map_err(|e| match row.get::<_, u32>(0) {
+            Ok(id) => StoreError::Internal(format!("car with id={} is unreadable: {}", id, e)),
+            Err(_) => e.into(),
+        }))
+    })?;
+    car_iter
+        .map(|car| car.map_err(StoreError::from)?)
+        .collect::<Result<Vec<Car>, _>>()
+}
+
+fn list_cars(conn: &mut Connection, query: &CarQuery) -> Result<CarPage, StoreError> {
+    let mut conds: Vec<String> = vec![];
+    let mut params: Vec<Value> = vec![];
+    if let Some(brand) = &query.brand {
+        conds.push("brand = ?".to_owned());
+        params.push(Value::Text(brand.clone()));
+    }
+    if let Some(model) = &query.model {
+        conds.push("model = ?".to_owned());
+        params.push(Value::Text(model.clone()));
+    }
+    if let Some(year) = query.year_gte {
+        conds.push("year >= ?".to_owned());
+        params.push(Value::Integer(year.into()));
+    }
+    if let Some(year) = query.year_lte {
+        conds.push("year <= ?".to_owned());
+        params.push(Value::Integer(year.into()));
+    }
+
+    let column = query.sort.field.column();
+    let (op, dir) = if query.sort.desc {
+        ("<", "DESC")
+    } else {
+        (">", "ASC")
+    };
+    if let Some(cursor) = &query.cursor {
+        conds.push(format!("({column}, id) {op} (?, ?)"));
+        params.push(match &cursor.key {
+            SortKey::Int(n) => Value::Integer((*n).into()),
+            SortKey::Text(s) => Value::Text(s.clone()),
+        });
+        params.push(Value::Integer(cursor.id.into()));
+    }
+
+    let mut sql = format!("SELECT {CAR_COLUMNS} FROM cars");
+    if !conds.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conds.join(" AND "));
+    }
+    sql.push_str(&format!(" ORDER BY {column} {dir}, id {dir} LIMIT ?"));
+    // fetch one more row to find out whether there is a next page
+    params.push(Value::Integer((query.limit + 1) as i64));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let car_iter = stmt.query_map(rusqlite::params_from_iter(params), car_from_row)?;
+    let cars = car_iter.collect::<Result<Vec<Car>, _>>()?;
+    Ok(CarPage::new(cars, query))
+}
+
+fn delete_car(conn: &mut Connection, id: u32, expected: Option<u32>) -> Result<(), StoreError> {
+    let n = conn.execute(
+        "DELETE FROM cars WHERE id=?1 AND (?2 IS NULL OR revision=?2)",
+        rusqlite::params![id, expected],
+    )?;
+    match n {
+        0 => Err(write_missed(conn, id, expected)),
+        _ => Ok(()),
+    }
+}
+
+fn delete_all_cars(conn: &mut Connection) -> Result<(), StoreError> {
+    conn.execute("DELETE FROM cars", ())?;
+    Ok(())
+}
+
+impl CarStore for SQLiteCarStore {
+    fn create_car(&self, car: Car) -> std::result::Result<u32, StoreError> {
+        self.pool.run_blocking(move |conn| create_car(conn, car))
+    }
+
+    fn create_cars(&self, cars: Vec<Car>) -> Result<Vec<u32>, StoreError> {
+        self.pool.run_blocking(move |conn| create_cars(conn, cars))
+    }
+
+    fn update_car(&self, car: Car, expected: Option<u32>) -> Result<Car, StoreError> {
+        self.pool
+            .run_blocking(move |conn| update_car(conn, car, expected))
+    }
+
+    fn patch_car(
+        &self,
+        id: u32,
+        patch: CarPatch,
+        expected: Option<u32>,
+    ) -> Result<Car, StoreError> {
+        self.pool
+            .run_blocking(move |conn| patch_car(conn, id, patch, expected))
+    }
+
+    fn get_car(&self, id: u32) -> Result<Car, StoreError> {
+        self.pool.run_blocking(move |conn| get_car(conn, id))
+    }
+
+    fn get_all_cars(&self) -> std::result::Result<Vec<Car>, StoreError> {
+        self.pool.run_blocking(get_all_cars)
+    }
+
+    fn list_cars(&self, query: &CarQuery) -> Result<CarPage, StoreError> {
+        let query = query.clone();
+        self.pool.run_blocking(move |conn| list_cars(conn, &query))
+    }
+
+    fn delete_car(&self, id: u32, expected: Option<u32>) -> Result<(), StoreError> {
+        self.pool
+            .run_blocking(move |conn| delete_car(conn, id, expected))
+    }
+
+    fn delete_all_cars(&self) -> Result<(), StoreError> {
+        self.pool.run_blocking(delete_all_cars)
+    }
+}
+
+impl AsyncCarStore for SQLiteCarStore {
+    fn create_car(&self, car: Car) -> BoxFuture<'_, Result<u32, StoreError>> {
+        Box::pin(self.pool.run(move |conn| create_car(conn, car)))
+    }
+
+    fn create_cars(&self, cars: Vec<Car>) -> BoxFuture<'_, Result<Vec<u32>, StoreError>> {
+        Box::pin(self.pool.run(move |conn| create_cars(conn, cars)))
+    }
+
+    fn update_car(
+        &self,
+        car: Car,
+        expected: Option<u32>,
+    ) -> BoxFuture<'_, Result<Car, StoreError>> {
+        Box::pin(self.pool.run(move |conn| update_car(conn, car, expected)))
+    }
+
+    fn patch_car(
+        &self,
+        id: u32,
+        patch: CarPatch,
+        expected: Option<u32>,
+    ) -> BoxFuture<'_, Result<Car, StoreError>> {
+        Box::pin(
+            self.pool
+                .run(move |conn| patch_car(conn, id, patch, expected)),
+        )
+    }
+
+    fn get_car(&self, id: u32) -> BoxFuture<'_, Result<Car, StoreError>> {
+        Box::pin(self.pool.run(move |conn| get_car(conn, id)))
+    }
+
+    fn get_all_cars(&self) -> BoxFuture<'_, Result<Vec<Car>, StoreError>> {
+        Box::pin(self.pool.run(get_all_cars))
+    }
+
+    fn list_cars(&self, query: CarQuery) -> BoxFuture<'_, Result<CarPage, StoreError>> {
+        Box::pin(self.pool.run(move |conn| list_cars(conn, &query)))
+    }
+
+    fn delete_car(&self, id: u32, expected: Option<u32>) -> BoxFuture<'_, Result<(), StoreError>> {
+        Box::pin(self.pool.run(move |conn| delete_car(conn, id, expected)))
+    }
+
+    fn delete_all_cars(&self) -> BoxFuture<'_, Result<(), StoreError>> {
+        Box::pin(self.pool.run(delete_all_cars))
+    }
+}
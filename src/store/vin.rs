@@ -0,0 +1,47 @@
+/// Validate a 17 character Vehicle Identification Number, returning it in upper case.
+///
+/// The check digit at position 9 is computed as in North America (49 CFR 565): every
+/// character is transliterated to a number, weighted by its position and summed modulo 11.
+pub fn normalize_vin(vin: &str) -> Result<String, String> {
+    const WEIGHTS: [u32; 17] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+    let vin = vin.trim().to_ascii_uppercase();
+    if vin.chars().count() != 17 {
+        return Err(format!("invalid vin={}, expect 17 characters", vin));
+    }
+    let mut sum = 0;
+    for (i, c) in vin.chars().enumerate() {
+        let value = transliterate(c)
+            .ok_or_else(|| format!("invalid vin={}, unexpected character {:?}", vin, c))?;
+        sum += value * WEIGHTS[i];
+    }
+    let check = match sum % 11 {
+        10 => 'X',
+        n => char::from_digit(n, 10).unwrap(),
+    };
+    match vin.chars().nth(8) {
+        Some(c) if c == check => Ok(vin),
+        _ => Err(format!(
+            "invalid vin={}, check digit should be {}",
+            vin, check
+        )),
+    }
+}
+
+/// I, O and Q are never used, they are too easily mistaken for 1 and 0.
+fn transliterate(c: char) -> Option<u32> {
+    let value = match c {
+        '0'..='9' => c.to_digit(10).unwrap(),
+        'A' | 'J' => 1,
+        'B' | 'K' | 'S' => 2,
+        'C' | 'L' | 'T' => 3,
+        'D' | 'M' | 'U' => 4,
+        'E' | 'N' | 'V' => 5,
+        'F' | 'W' => 6,
+        'G' | 'P' | 'X' => 7,
+        'H' | 'Y' => 8,
+        'R' | 'Z' => 9,
+        _ => return None,
+    };
+    Some(value)
+}
@@ -0,0 +1,244 @@
+use crate::middleware::auth::ClientCert;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+use tokio_rustls::TlsAcceptor;
+
+/// How often the certificate files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the PEM files of the server certificate are, and of the CA of client certificates if
+/// clients may authenticate with one.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// certificate chain, the server certificate first
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+    /// reject clients without a certificate of `client_ca`
+    pub require_client_cert: bool,
+}
+
+struct Loaded {
+    modified: (Option<SystemTime>, Option<SystemTime>),
+    key: Arc<CertifiedKey>,
+}
+
+/// The server certificate, reloaded for the next handshakes when its files change, see
+/// [`ReloadingCert::spawn_reload`].
+struct ReloadingCert {
+    cert: PathBuf,
+    key: PathBuf,
+    provider: Arc<CryptoProvider>,
+    loaded: RwLock<Loaded>,
+}
+
+impl std::fmt::Debug for ReloadingCert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadingCert")
+            .field("cert", &self.cert)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl ReloadingCert {
+    fn load(cert: &Path, key: &Path, provider: Arc<CryptoProvider>) -> Result<Self, String> {
+        let modified = Self::modified(cert, key);
+        let certified = Self::read(cert, key, &provider)?;
+        Ok(ReloadingCert {
+            cert: cert.to_owned(),
+            key: key.to_owned(),
+            provider,
+            loaded: RwLock::new(Loaded {
+                modified,
+                key: Arc::new(certified),
+            }),
+        })
+    }
+
+    fn modified(cert: &Path, key: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        (modified(cert), modified(key))
+    }
+
+    fn read(cert: &Path, key: &Path, provider: &CryptoProvider) -> Result<CertifiedKey, String> {
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("failed to read {}: {}", cert.display(), e))?;
+        if chain.is_empty() {
+            return Err(format!("no certificate in {}", cert.display()));
+        }
+        let private_key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| format!("failed to read {}: {}", key.display(), e))?;
+        CertifiedKey::from_der(chain, private_key, provider).map_err(|e| {
+            format!(
+                "{} does not go with {}: {}",
+                key.display(),
+                cert.display(),
+                e
+            )
+        })
+    }
+
+    /// Read the files again if either has changed; while they are half written, e.g. the
+    /// new certificate with the old key, the certificate loaded before is kept.
+    fn reload_if_changed(&self) {
+        let modified = Self::modified(&self.cert, &self.key);
+        if modified == self.loaded.read().unwrap().modified {
+            return;
+        }
+        match Self::read(&self.cert, &self.key, &self.provider) {
+            Ok(key) => {
+                info!("reloaded the certificate {}", self.cert.display());
+                *self.loaded.write().unwrap() = Loaded {
+                    modified,
+                    key: Arc::new(key),
+                };
+            }
+            Err(e) => error!(
+                "failed to reload, keeping the certificate loaded before: {}",
+                e
+            ),
+        }
+    }
+
+    /// Check the files every [`RELOAD_INTERVAL`] off the tokio workers, as long as the
+    /// certificate is in use, so that handshakes never wait for the file system.
+    fn spawn_reload(self: &Arc<Self>) {
+        let cert = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(cert) = cert.upgrade() else { return };
+                let _ = tokio::task::spawn_blocking(move || cert.reload_if_changed()).await;
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.loaded.read().unwrap().key.clone())
+    }
+}
+
+/// Accept TLS connections offering `h2` and `http/1.1` by ALPN.
+///
+/// With a client CA, a client may present a certificate, which must then be issued by the CA;
+/// clients without one are still accepted, to authenticate in another way, unless a client
+/// certificate is required.
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match &config.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?
+            {
+                let cert = cert.map_err(|e| format!("{}: {}", path.display(), e))?;
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+            let mut verifier =
+                WebPkiClientVerifier::builder_with_provider(roots.into(), provider.clone());
+            if !config.require_client_cert {
+                verifier = verifier.allow_unauthenticated();
+            }
+            let verifier = verifier
+                .build()
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let resolver = Arc::new(ReloadingCert::load(&config.cert, &config.key, provider)?);
+    resolver.spawn_reload();
+    let mut server_config = builder.with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// The certificate the client presented, verified by the handshake already.
+pub fn client_cert(conn: &ServerConnection) -> Option<ClientCert> {
+    let der = conn.peer_certificates()?.first()?;
+    match ClientCert::from_der(der) {
+        Ok(cert) => Some(cert),
+        Err(e) => {
+            warn!("{}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReloadingCert;
+    use crate::middleware::auth::ClientCert;
+    use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+    use std::sync::Arc;
+
+    fn self_signed(cn: &str) -> (String, String, Vec<u8>) {
+        let mut params = CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::CommonName, cn);
+        dn.push(DnType::OrganizationName, "ops");
+        params.distinguished_name = dn;
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        (cert.pem(), key.serialize_pem(), cert.der().to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_reload_cert() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let (cert, key, der) = self_signed("server");
+        std::fs::write(&cert_path, cert).unwrap();
+        std::fs::write(&key_path, key).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let resolver = Arc::new(ReloadingCert::load(&cert_path, &key_path, provider).unwrap());
+        let served = || resolver.loaded.read().unwrap().key.cert[0].to_vec();
+        assert_eq!(served(), der);
+
+        std::thread::sleep(super::RELOAD_INTERVAL);
+        // a certificate without its key yet is not taken
+        let (cert, key, der) = self_signed("renewed");
+        std::fs::write(&cert_path, cert).unwrap();
+        resolver.reload_if_changed();
+        assert_ne!(served(), der);
+
+        std::fs::write(&key_path, key).unwrap();
+        resolver.reload_if_changed();
+        assert_eq!(served(), der);
+
+        // in the background, once the files change
+        resolver.spawn_reload();
+        std::thread::sleep(super::RELOAD_INTERVAL);
+        let (cert, key, again) = self_signed("again");
+        std::fs::write(&cert_path, cert).unwrap();
+        std::fs::write(&key_path, key).unwrap();
+        tokio::time::sleep(super::RELOAD_INTERVAL * 2).await;
+        assert_eq!(served(), again);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let client = ClientCert::from_der(&der).unwrap();
+        assert_eq!(client.common_name.as_deref(), Some("renewed"));
+        assert_eq!(client.organizations, ["ops"]);
+    }
+}